@@ -1,26 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
 use color_eyre::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute};
 use ratatui::style::{Color, Style};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::Borders;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::Stylize,
-    widgets::{Block, Clear, List, ListItem, ListState, Paragraph},
+    widgets::{
+        Block, Clear, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Wrap,
+    },
     DefaultTerminal, Frame,
 };
 
-use crate::chezmoi;
+use crate::ansi;
+use crate::chezmoi::{self, AddOption, ChezmoiBackend};
+use crate::config::Config;
+use crate::diff_cache::DiffCache;
+use crate::discard::{self, DiscardMethod, DiscardPlan, DiscardSide};
+use crate::filters::{FilterItem, Filters};
+use crate::fuzzy;
+use crate::git::{Commit, GitCommand, GitEntry, GitStatus};
+use crate::hunks::{self, Hunk, PatchSide};
+use crate::plan::{ExecutionPlan, PlanAction};
+use crate::split_diff::{self, LineKind, SideLine, SplitRow};
+use crate::syntax::Syntax;
+use crate::tree::{self, Row};
 use crate::utils::FileStatus;
+use crate::worker::{FileResults, Job, JobOutput, PreviewKind, Worker};
+
+/// How long to wait for input before checking on background jobs again.
+const TICK_RATE: Duration = Duration::from_millis(100);
+
+/// How many entries above and below the highlight get their diff fetched ahead of time.
+const PREFETCH_RADIUS: usize = 2;
+
+/// Height of the staging panes, borders included.
+const STAGE_HEIGHT: u16 = 8;
+
+/// Backgrounds of added and removed lines under syntax highlighting.
+const ADDED_BG: Color = Color::Indexed(22);
+const REMOVED_BG: Color = Color::Indexed(52);
+
+const SPINNER: [&str; 8] = ["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"];
 
 #[derive(Debug, Clone)]
 pub enum PopupAction {
-    Apply,
-    ReAdd,
+    Forget,
+    Chattr(&'static str),
+    Discard(DiscardSide),
+    /// Pull the source repository, then review it or apply everything.
+    Update {
+        apply_all: bool,
+    },
     Cancel,
 }
 
-#[derive(Debug, Default, PartialEq)]
+/// Attribute changes offered by the chattr popup, as passed to `chezmoi chattr`.
+const CHATTR_ATTRIBUTES: [&str; 12] = [
+    "+template",
+    "-template",
+    "+private",
+    "-private",
+    "+executable",
+    "-executable",
+    "+readonly",
+    "-readonly",
+    "+encrypted",
+    "-encrypted",
+    "+exact",
+    "-exact",
+];
+
+/// Which list is shown in the left pane.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum View {
+    /// Entries reported by `chezmoi status`.
+    #[default]
+    Status,
+    /// Everything reported by `chezmoi managed`, in sync or not.
+    Managed,
+    /// Files of the chosen directory reported by `chezmoi unmanaged`.
+    Unmanaged,
+}
+
+impl View {
+    const ALL: [View; 3] = [View::Status, View::Managed, View::Unmanaged];
+
+    fn title(&self) -> &'static str {
+        match self {
+            View::Status => "Status",
+            View::Managed => "Managed",
+            View::Unmanaged => "Unmanaged",
+        }
+    }
+}
+
+/// Which pane receives navigation keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Focus {
+    #[default]
+    List,
+    Diff,
+}
+
+/// A command that takes over the terminal while it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InteractiveCommand {
+    /// `chezmoi edit <file>`
+    Edit(String),
+    /// `chezmoi edit`, opening the source directory.
+    OpenSource,
+    /// `chezmoi merge <file>`
+    Merge(String),
+    /// The configured git UI, e.g. lazygit, on the source directory.
+    GitUi,
+}
+
+/// Staging panes below the status list, fed by the selection of the status view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Files selected with [`Selection::Local`].
+    ReAdd,
+    /// Files selected with [`Selection::Source`].
+    Apply,
+}
+
+impl Stage {
+    fn title(&self) -> &'static str {
+        match self {
+            Stage::ReAdd => "To re-add",
+            Stage::Apply => "To apply",
+        }
+    }
+
+    fn selection(&self) -> Selection {
+        match self {
+            Stage::ReAdd => Selection::Local,
+            Stage::Apply => Selection::Source,
+        }
+    }
+}
+
+/// Per-file outcome of the last execute, for each staging pane.
+#[derive(Debug)]
+pub struct BatchResults {
+    re_add: FileResults,
+    apply: FileResults,
+}
+
+impl BatchResults {
+    fn get(&self, stage: Stage) -> &FileResults {
+        match stage {
+            Stage::ReAdd => &self.re_add,
+            Stage::Apply => &self.apply,
+        }
+    }
+
+    fn failed_count(&self) -> usize {
+        self.re_add
+            .iter()
+            .chain(&self.apply)
+            .filter(|(_, result)| matches!(result, Some(Err(_))))
+            .count()
+    }
+}
+
+/// Succeeded, failed and skipped files of a batch.
+fn count_results(results: &FileResults) -> (usize, usize, usize) {
+    results.iter().fold(
+        (0, 0, 0),
+        |(ok, failed, skipped), (_, result)| match result {
+            Some(Ok(())) => (ok + 1, failed, skipped),
+            Some(Err(_)) => (ok, failed + 1, skipped),
+            None => (ok, failed, skipped + 1),
+        },
+    )
+}
+
+/// How the status list is laid out.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ViewMode {
+    #[default]
+    Flat,
+    Tree,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub enum Selection {
     #[default]
     None,
@@ -36,33 +209,529 @@ pub struct FileItem {
     pub(crate) source_status: FileStatus,
 }
 
-#[derive(Debug, Default)]
+impl FileItem {
+    pub fn is_script(&self) -> bool {
+        self.local_status == FileStatus::Run || self.source_status == FileStatus::Run
+    }
+
+    /// Changed both locally and in the source state: re-adding loses the source changes and
+    /// applying loses the local ones, so the file needs a merge.
+    pub fn is_conflict(&self) -> bool {
+        self.local_status != FileStatus::Unchanged
+            && self.source_status != FileStatus::Unchanged
+            && !self.is_script()
+    }
+
+    /// Selection picked when the file is first selected: re-add if it changed locally,
+    /// apply otherwise.
+    fn first_selection(&self) -> Selection {
+        if self.local_status != FileStatus::Unchanged {
+            Selection::Local
+        } else {
+            Selection::Source
+        }
+    }
+
+    /// Cycles none -> local -> source -> none, skipping sides without changes.
+    fn next_selection(&self) -> Selection {
+        match self.selected {
+            Selection::None => self.first_selection(),
+            Selection::Local => {
+                if self.source_status != FileStatus::Unchanged {
+                    Selection::Source
+                } else {
+                    Selection::None
+                }
+            }
+            Selection::Source => Selection::None,
+        }
+    }
+}
+
+/// Symbol and colour of a status column in the status list.
+fn status_symbol(status: &FileStatus) -> (&'static str, Style) {
+    match status {
+        FileStatus::Added => ("A", Style::default().fg(Color::Green)),
+        FileStatus::Modified => ("M", Style::default().fg(Color::Yellow)),
+        FileStatus::Deleted => ("D", Style::default().fg(Color::Red)),
+        FileStatus::Untracked => ("?", Style::default().fg(Color::Red)),
+        FileStatus::Run => ("R", Style::default().fg(Color::Magenta)),
+        FileStatus::Unchanged => (" ", Style::default()),
+    }
+}
+
+/// A rectangle of at most `width`x`height` centered in `area`.
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// `line` with every occurrence of `query` highlighted, ignoring ASCII case.
+fn highlight_matches(line: &str, query: &str) -> Line<'static> {
+    let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let lowercase = line.to_ascii_lowercase();
+    let query = query.to_ascii_lowercase();
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for (i, _) in lowercase.match_indices(&query) {
+        if i < start {
+            continue;
+        }
+        spans.push(Span::raw(line[start..i].to_string()));
+        spans.push(Span::styled(
+            line[i..i + query.len()].to_string(),
+            match_style,
+        ));
+        start = i + query.len();
+    }
+    spans.push(Span::raw(line[start..].to_string()));
+    Line::from(spans)
+}
+
+/// Style of each character of `text`, with matches of `query` (ignoring ASCII case) on top.
+fn char_styles(text: &str, style_at: impl Fn(usize) -> Style, query: &str) -> Vec<(char, Style)> {
+    let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let matches: Vec<usize> = if query.is_empty() {
+        Vec::new()
+    } else {
+        text.to_ascii_lowercase()
+            .match_indices(&query.to_ascii_lowercase())
+            .map(|(i, _)| i)
+            .collect()
+    };
+    text.char_indices()
+        .map(|(i, c)| {
+            let matched = matches.iter().any(|&m| (m..m + query.len()).contains(&i));
+            (c, if matched { match_style } else { style_at(i) })
+        })
+        .collect()
+}
+
+/// Style of byte `i` of a line highlighted by [`Syntax::highlight`], on top of `base`.
+fn syntax_style(highlights: &[(std::ops::Range<usize>, Style)], i: usize, base: Style) -> Style {
+    highlights
+        .iter()
+        .rev()
+        .find(|(range, _)| range.contains(&i))
+        .map_or(base, |(_, style)| base.patch(*style))
+}
+
+/// Groups characters of the same style into spans.
+fn group_spans(chars: Vec<(char, Style)>) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span> = Vec::new();
+    for (c, style) in chars {
+        match spans.last_mut() {
+            Some(last) if last.style == style => last.content.to_mut().push(c),
+            _ => spans.push(Span::styled(c.to_string(), style)),
+        }
+    }
+    spans
+}
+
+/// A line of a unified diff hunk (or of a preview when `prefixed` is false) with syntax
+/// highlighting over the added/removed background.
+fn syntax_line(line: &str, syntax: &Syntax, prefixed: bool, query: &str) -> Line<'static> {
+    let (prefix, content) = match line.chars().next() {
+        Some(c) if prefixed => line.split_at(c.len_utf8()),
+        _ => ("", line),
+    };
+    let (base, prefix_style) = match prefix {
+        "+" => (
+            Style::default().bg(ADDED_BG),
+            Style::default().fg(Color::Green).bg(ADDED_BG),
+        ),
+        "-" => (
+            Style::default().bg(REMOVED_BG),
+            Style::default().fg(Color::Red).bg(REMOVED_BG),
+        ),
+        _ => (Style::default(), Style::default()),
+    };
+    let highlights = syntax.highlight(content);
+    let mut spans = vec![Span::styled(prefix.to_string(), prefix_style)];
+    spans.extend(group_spans(char_styles(
+        content,
+        |i| syntax_style(&highlights, i, base),
+        query,
+    )));
+    Line::from(spans).style(base)
+}
+
+/// Groups characters of the same style into spans, expanding tabs and cutting or padding
+/// the text to `width` columns.
+fn fit_spans(chars: Vec<(char, Style)>, width: usize) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut column = 0;
+    for (c, style) in chars {
+        let text = if c == '\t' {
+            " ".repeat(4 - column % 4)
+        } else {
+            c.to_string()
+        };
+        let text_width = text.chars().count();
+        if column + text_width > width {
+            break;
+        }
+        column += text_width;
+        match spans.last_mut() {
+            Some(last) if last.style == style => last.content.to_mut().push_str(&text),
+            _ => spans.push(Span::styled(text, style)),
+        }
+    }
+    spans.push(Span::raw(" ".repeat(width - column)));
+    spans
+}
+
+/// One side of a row of the side-by-side diff, `width` columns wide.
+fn split_side_spans(
+    line: Option<&SideLine>,
+    width: usize,
+    number_width: usize,
+    syntax: Option<&Syntax>,
+    query: &str,
+) -> Vec<Span<'static>> {
+    let Some(line) = line else {
+        return vec![Span::raw(" ".repeat(width))];
+    };
+    let (style, changed_style) = match (line.kind, syntax) {
+        (LineKind::Removed, Some(_)) => (
+            Style::default().bg(REMOVED_BG),
+            Style::default().fg(Color::Black).bg(Color::Red),
+        ),
+        (LineKind::Added, Some(_)) => (
+            Style::default().bg(ADDED_BG),
+            Style::default().fg(Color::Black).bg(Color::Green),
+        ),
+        _ => match line.kind {
+            LineKind::Context => (Style::default(), Style::default()),
+            LineKind::Removed => (
+                Style::default().fg(Color::Red),
+                Style::default().fg(Color::Black).bg(Color::Red),
+            ),
+            LineKind::Added => (
+                Style::default().fg(Color::Green),
+                Style::default().fg(Color::Black).bg(Color::Green),
+            ),
+        },
+    };
+    let highlights = syntax
+        .map(|syntax| syntax.highlight(&line.text))
+        .unwrap_or_default();
+    let number = format!("{:>1$} ", line.number, number_width);
+    let text_width = width.saturating_sub(number.len());
+    let chars = char_styles(
+        &line.text,
+        |i| {
+            if line.changed.iter().any(|range| range.contains(&i)) {
+                changed_style
+            } else {
+                syntax_style(&highlights, i, style)
+            }
+        },
+        query,
+    );
+    let mut spans = vec![Span::from(number).dark_gray()];
+    spans.extend(fit_spans(chars, text_width));
+    spans
+}
+
+/// A row of the side-by-side diff, `width` columns wide.
+fn split_row_line(
+    row: &SplitRow,
+    width: usize,
+    number_width: usize,
+    syntax: Option<&Syntax>,
+    query: &str,
+) -> Line<'static> {
+    match row {
+        SplitRow::Header { text, .. } => {
+            let style = if text.starts_with("@@") {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            Line::from(fit_spans(char_styles(text, |_| style, query), width))
+        }
+        SplitRow::Pair { old, new, .. } => {
+            let half = width.saturating_sub(1) / 2;
+            let mut spans = split_side_spans(old.as_ref(), half, number_width, syntax, query);
+            spans.push(Span::from("│").dark_gray());
+            spans.extend(split_side_spans(
+                new.as_ref(),
+                width.saturating_sub(half + 1),
+                number_width,
+                syntax,
+                query,
+            ));
+            Line::from(spans)
+        }
+    }
+}
+
+/// Lines of a unified diff with the prefix of the hunk lines (` `, `+` or `-`). File headers
+/// are told apart by their position, before the first `@@` of a file, since a removed `--`
+/// comment looks like a `---` header.
+fn hunk_line_prefixes(diff: &str) -> Vec<(&str, Option<char>)> {
+    let mut in_hunk = false;
+    diff.lines()
+        .map(|line| {
+            if line.starts_with("diff ") {
+                in_hunk = false;
+            } else if line.starts_with("@@") {
+                in_hunk = true;
+                return (line, None);
+            }
+            (line, line.chars().next().filter(|_| in_hunk))
+        })
+        .collect()
+}
+
+/// Leaves the alternate screen and raw mode while `f` runs, e.g. to open an editor, and
+/// restores them afterwards.
+fn with_suspended_terminal<T>(terminal: &mut DefaultTerminal, f: impl FnOnce() -> T) -> Result<T> {
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, cursor::Show)?;
+
+    let result = f();
+
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    terminal::enable_raw_mode()?;
+    // The screen content is gone, redraw everything
+    terminal.clear()?;
+    Ok(result)
+}
+
+/// Draws a scrollable modal over most of the screen, with its keys at the bottom.
+fn draw_modal(frame: &mut Frame, title: Line, help: Vec<Span>, lines: Vec<Line>, scroll: u16) {
+    let area = frame.area();
+    let popup_area = centered_rect(area, area.width * 4 / 5, area.height * 4 / 5);
+
+    frame.render_widget(Clear, popup_area);
+
+    let block = Block::bordered()
+        .title(title)
+        .title_bottom(Line::from(help));
+    frame.render_widget(
+        Paragraph::new(lines).block(block).scroll((scroll, 0)),
+        popup_area,
+    );
+}
+
+/// A modal with confirm/cancel keys.
+fn draw_confirm_modal(
+    frame: &mut Frame,
+    title: Line,
+    confirm_label: &str,
+    lines: Vec<Line>,
+    scroll: u16,
+) {
+    let help = vec![
+        "y/<enter>".blue().bold(),
+        format!(" {}", confirm_label).gray(),
+        " | ".dark_gray(),
+        "n/<esc>".blue().bold(),
+        " Cancel".gray(),
+        " | ".dark_gray(),
+        "j/k".blue().bold(),
+        " Scroll".gray(),
+    ];
+    draw_modal(frame, title, help, lines, scroll);
+}
+
+/// Moves a list highlight one step, wrapping around at both ends.
+fn step_list(state: &mut ListState, len: usize, forward: bool) {
+    if len == 0 {
+        return;
+    }
+    let i = match state.selected() {
+        Some(i) if forward => (i + 1) % len,
+        Some(i) => (i + len - 1) % len,
+        None => 0,
+    };
+    state.select(Some(i));
+}
+
+#[derive(Debug)]
 pub struct App {
     running: bool,
+    backend: Arc<dyn ChezmoiBackend>,
+    worker: Worker,
     pub files: Vec<FileItem>,
+    managed_files: Vec<FileItem>,
+    managed_loaded: bool,
+    unmanaged_files: Vec<FileItem>,
+    unmanaged_loaded: bool,
+    /// Directory browsed by the unmanaged view, relative to the destination directory.
+    unmanaged_dir: String,
+    /// Directory being typed for the unmanaged view, `None` when not prompting.
+    dir_input: Option<String>,
+    view: View,
+    /// Highlighted path of the views that are not shown, restored when switching back.
+    view_highlights: HashMap<View, String>,
+    rows: Vec<Row>,
+    view_mode: ViewMode,
+    collapsed_dirs: HashSet<String>,
+    filter_query: String,
+    filter_input: bool,
+    filters: Filters,
+    show_filter_popup: bool,
+    filter_popup_state: ListState,
+    add_options: Vec<AddOption>,
+    show_add_popup: bool,
+    add_popup_state: ListState,
+    /// Files the add popup acts on, captured when it was opened.
+    add_targets: Vec<String>,
+    /// Discard waiting for confirmation in the discard modal.
+    discard_plan: Option<DiscardPlan>,
+    /// Staged batches waiting for confirmation in the plan modal.
+    execution_plan: Option<ExecutionPlan>,
+    modal_scroll: u16,
+    batch_results: Option<BatchResults>,
+    show_batch_results: bool,
+    /// Git panel over the source repository, with the last `git status` it loaded.
+    show_git_panel: bool,
+    git_status: Option<GitStatus>,
+    git_list_state: ListState,
+    /// Commit message being typed in the git panel, `None` when not committing.
+    commit_message: Option<String>,
+    /// Commits brought in by the last update, shown for review with the files to apply.
+    update_commits: Option<Vec<Commit>>,
+    /// Stage every source change for apply and plan it once the status is refreshed.
+    apply_all_after_refresh: bool,
+    /// Run by the event loop on its next iteration, with the TUI suspended.
+    interactive_command: Option<InteractiveCommand>,
     chezmoi_file_diff: String,
+    /// `chezmoi_file_diff` as coloured by the external diff tool, when enabled.
+    rendered_diff: Option<Vec<Line<'static>>>,
+    config: Config,
+    focus: Focus,
+    /// Hunks of the shown diff, navigated when the diff pane has focus.
+    hunks: Vec<Hunk>,
+    hunk_cursor: usize,
+    selected_hunks: HashSet<usize>,
+    /// First line of the diff pane, in rows of the split layout when it is shown.
+    diff_scroll: u16,
+    /// Shows diffs side by side instead of unified.
+    split_view: bool,
+    /// Side-by-side layout of the shown diff, empty for previews.
+    split_rows: Vec<SplitRow>,
+    /// Lines of the diff pane that fit on screen, as of the last draw.
+    diff_height: u16,
+    /// Search typed in the diff pane, being edited while `diff_search_input` is set.
+    diff_search: String,
+    diff_search_input: bool,
+    /// Diff lines matching `diff_search`.
+    diff_matches: Vec<usize>,
+    /// Index in `diff_matches` of the match last jumped to.
+    diff_match_cursor: Option<usize>,
+    diff_cache: DiffCache,
+    diff_generation: u64,
+    diff_loading: bool,
+    bulk_diff: bool,
+    bulk_diff_pending: bool,
+    busy_label: Option<String>,
+    spinner_tick: usize,
     list_state: ListState,
     error_message: Option<String>,
     show_popup: bool,
+    popup_title: String,
     popup_items: Vec<(String, PopupAction)>, // Tuple of display string and action
     popup_state: ListState,
+    /// Files the open popup acts on, captured when it was opened.
+    popup_targets: Vec<String>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
     pub fn new() -> Self {
+        Self::with_backend(Arc::new(chezmoi::ProcessBackend))
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Builds the app on top of any chezmoi backend, e.g. a [`chezmoi::ScriptedBackend`].
+    pub fn with_backend(backend: Arc<dyn ChezmoiBackend>) -> Self {
         let mut app = Self {
             running: false,
+            worker: Worker::new(Arc::clone(&backend)),
+            backend,
             files: Vec::new(),
+            managed_files: Vec::new(),
+            managed_loaded: false,
+            unmanaged_files: Vec::new(),
+            unmanaged_loaded: false,
+            unmanaged_dir: String::new(),
+            dir_input: None,
+            view: View::default(),
+            view_highlights: HashMap::new(),
+            rows: Vec::new(),
+            view_mode: ViewMode::default(),
+            collapsed_dirs: HashSet::new(),
+            filter_query: String::new(),
+            filter_input: false,
+            filters: Filters::default(),
+            show_filter_popup: false,
+            filter_popup_state: ListState::default(),
+            add_options: Vec::new(),
+            show_add_popup: false,
+            add_popup_state: ListState::default(),
+            add_targets: Vec::new(),
+            discard_plan: None,
+            execution_plan: None,
+            modal_scroll: 0,
+            batch_results: None,
+            show_batch_results: false,
+            show_git_panel: false,
+            git_status: None,
+            git_list_state: ListState::default(),
+            commit_message: None,
+            update_commits: None,
+            apply_all_after_refresh: false,
+            interactive_command: None,
             chezmoi_file_diff: String::new(),
+            rendered_diff: None,
+            config: Config::default(),
+            focus: Focus::default(),
+            hunks: Vec::new(),
+            hunk_cursor: 0,
+            selected_hunks: HashSet::new(),
+            diff_scroll: 0,
+            split_view: false,
+            split_rows: Vec::new(),
+            diff_height: 0,
+            diff_search: String::new(),
+            diff_search_input: false,
+            diff_matches: Vec::new(),
+            diff_match_cursor: None,
+            diff_cache: DiffCache::default(),
+            diff_generation: 0,
+            diff_loading: false,
+            bulk_diff: false,
+            bulk_diff_pending: false,
+            busy_label: None,
+            spinner_tick: 0,
             list_state: ListState::default(),
             error_message: None,
             show_popup: false,
+            popup_title: String::new(),
             popup_items: Vec::new(),
             popup_state: ListState::default(),
+            popup_targets: Vec::new(),
         };
-        app.files = chezmoi::update_status();
-        app.list_state.select(Some(0));
-        app.update_selected_diff();
+        app.refresh_status();
         app
     }
 
@@ -70,12 +739,82 @@ impl App {
     // Helper methods
     // --------------------------------------------------------
 
+    /// Files listed by the current view.
+    fn current_files(&self) -> &[FileItem] {
+        match self.view {
+            View::Status => &self.files,
+            View::Managed => &self.managed_files,
+            View::Unmanaged => &self.unmanaged_files,
+        }
+    }
+
+    fn current_files_mut(&mut self) -> &mut [FileItem] {
+        match self.view {
+            View::Status => &mut self.files,
+            View::Managed => &mut self.managed_files,
+            View::Unmanaged => &mut self.unmanaged_files,
+        }
+    }
+
+    fn get_highlighted_row(&self) -> Option<&Row> {
+        self.list_state.selected().and_then(|i| self.rows.get(i))
+    }
+
+    /// Path of the highlighted file, empty when nothing or a directory is highlighted.
     fn get_highlighted_file(&self) -> String {
-        self.list_state
-            .selected()
-            .and_then(|i| self.files.get(i))
-            .map(|file| file.path.clone())
-            .unwrap_or_default()
+        match self.get_highlighted_row() {
+            Some(Row::File { index, .. }) => self.current_files()[*index].path.clone(),
+            _ => String::new(),
+        }
+    }
+
+    /// Whether a file passes the current filter. Hidden files keep their selection.
+    fn is_visible(&self, file: &FileItem) -> bool {
+        self.filters.matches(file) && fuzzy::fuzzy_match(&self.filter_query, &file.path).is_some()
+    }
+
+    fn visible_file_count(&self) -> usize {
+        self.current_files()
+            .iter()
+            .filter(|f| self.is_visible(f))
+            .count()
+    }
+
+    /// Recomputes the rows of the status list, keeping the highlight on the same path.
+    fn rebuild_rows(&mut self) {
+        self.rebuild_rows_at(self.get_highlighted_path());
+    }
+
+    fn get_highlighted_path(&self) -> Option<String> {
+        self.get_highlighted_row()
+            .map(|row| row.path(self.current_files()).to_string())
+    }
+
+    /// Recomputes the rows of the status list, moving the highlight to `highlighted_path`
+    /// if it is still listed.
+    fn rebuild_rows_at(&mut self, highlighted_path: Option<String>) {
+        let highlighted_index = self.list_state.selected().unwrap_or(0);
+
+        let files = self.current_files();
+        let indices = (0..files.len()).filter(|&i| self.is_visible(&files[i]));
+        let rows = match self.view_mode {
+            ViewMode::Flat => tree::flat_rows(indices),
+            ViewMode::Tree => tree::tree_rows(files, indices, &self.collapsed_dirs),
+        };
+        self.rows = rows;
+
+        if self.rows.is_empty() {
+            self.list_state.select(None);
+            return;
+        }
+        let index = highlighted_path
+            .and_then(|path| {
+                self.rows
+                    .iter()
+                    .position(|row| row.path(self.current_files()) == path)
+            })
+            .unwrap_or(highlighted_index.min(self.rows.len() - 1));
+        self.list_state.select(Some(index));
     }
 
     fn get_selected_local_files(&self) -> Vec<String> {
@@ -106,236 +845,2108 @@ impl App {
                             | FileStatus::Modified
                             | FileStatus::Deleted
                             | FileStatus::Untracked
+                            | FileStatus::Run
                     )
             })
             .map(|f| f.path.clone())
             .collect()
     }
 
-    fn update_selected_diff(&mut self) {
-        self.chezmoi_file_diff.clear();
-        if let Some(selected) = self.list_state.selected() {
-            if let Some(file) = self.files.get(selected) {
-                self.chezmoi_file_diff = chezmoi::diff(&file.path);
-            }
+    /// Queues a `chezmoi status` reload; the list is replaced once it completes. The managed
+    /// and unmanaged lists are reloaded too once they have been shown, so they stay current.
+    fn refresh_status(&mut self) {
+        self.busy_label = Some("Refreshing status".to_string());
+        self.worker.submit(Job::Status(self.filters.types.clone()));
+        if self.managed_loaded || self.view == View::Managed {
+            self.worker.submit(Job::Managed(self.filters.types.clone()));
         }
-    }
-
-    // --------------------------------------------------------
-    // Commands
-    // --------------------------------------------------------
-
-    fn toggle_selected_file(&mut self) {
-        if let Some(selected) = self.list_state.selected() {
-            if let Some(file) = self.files.get_mut(selected) {
-                file.selected = match file.selected {
-                    Selection::None => {
-                        if file.local_status != FileStatus::Unchanged {
-                            Selection::Local
-                        } else {
-                            Selection::Source
-                        }
-                    }
-                    Selection::Local => {
-                        if file.source_status != FileStatus::Unchanged {
-                            Selection::Source
-                        } else {
-                            Selection::None
-                        }
-                    }
-                    Selection::Source => Selection::None,
-                };
-            }
+        if self.unmanaged_loaded || self.view == View::Unmanaged {
+            self.worker
+                .submit(Job::Unmanaged(self.unmanaged_dir.clone()));
         }
     }
 
-    fn apply_selected_files(&mut self) {
-        let selected_files = self.get_selected_source_files();
-        if !selected_files.is_empty() {
-            match chezmoi::apply(&selected_files) {
-                Ok(_) => {
-                    for file in &mut self.files {
-                        file.selected = Selection::None;
-                    }
-                    self.files = chezmoi::update_status();
-                    self.update_selected_diff();
-                    self.error_message = None;
-                }
-                Err(e) => {
-                    self.error_message = Some(e.to_string());
+    /// Rebuilds the managed list from `chezmoi managed` paths, taking statuses from the last
+    /// `chezmoi status` and keeping selections.
+    fn set_managed_files(&mut self, paths: Vec<String>) {
+        let mut selections: HashMap<String, Selection> = self
+            .managed_files
+            .drain(..)
+            .map(|f| (f.path, f.selected))
+            .collect();
+        self.managed_files = paths
+            .into_iter()
+            .map(|path| {
+                let (local_status, source_status) = self
+                    .files
+                    .iter()
+                    .find(|f| f.path == path)
+                    .map(|f| (f.local_status.clone(), f.source_status.clone()))
+                    .unwrap_or((FileStatus::Unchanged, FileStatus::Unchanged));
+                FileItem {
+                    selected: selections.remove(&path).unwrap_or_default(),
+                    path,
+                    local_status,
+                    source_status,
                 }
-            }
-        }
+            })
+            .collect();
+        self.managed_loaded = true;
     }
 
-    fn re_add_selected_files(&mut self) {
-        let selected_files = self.get_selected_local_files();
-        if !selected_files.is_empty() {
-            match chezmoi::re_add(&selected_files) {
-                Ok(_) => {
-                    for file in &mut self.files {
-                        file.selected = Selection::None;
-                    }
-                    self.files = chezmoi::update_status();
-                    self.update_selected_diff();
-                    self.error_message = None;
-                }
-                Err(e) => {
-                    self.error_message = Some(e.to_string());
+    /// Rebuilds the unmanaged list from `chezmoi unmanaged` paths, keeping selections.
+    fn set_unmanaged_files(&mut self, paths: Vec<String>) {
+        let mut selections: HashMap<String, Selection> = self
+            .unmanaged_files
+            .drain(..)
+            .map(|f| (f.path, f.selected))
+            .collect();
+        self.unmanaged_files = paths
+            .into_iter()
+            .map(|path| FileItem {
+                selected: selections.remove(&path).unwrap_or_default(),
+                path,
+                local_status: FileStatus::Untracked,
+                source_status: FileStatus::Unchanged,
+            })
+            .collect();
+        self.unmanaged_loaded = true;
+    }
+
+    /// What the right pane shows for `path`: unmanaged files have no diff and are previewed
+    /// from disk, and files of the managed view that are in sync would have an empty diff.
+    fn preview_kind(&self, path: &str) -> PreviewKind {
+        match self.view {
+            View::Status => PreviewKind::Diff,
+            View::Managed => {
+                let in_sync = self
+                    .managed_files
+                    .iter()
+                    .find(|f| f.path == path)
+                    .is_some_and(|f| {
+                        f.local_status == FileStatus::Unchanged
+                            && f.source_status == FileStatus::Unchanged
+                    });
+                if in_sync {
+                    PreviewKind::Contents
+                } else {
+                    PreviewKind::Diff
                 }
             }
+            View::Unmanaged => PreviewKind::Target,
         }
     }
 
-    fn edit_highlighted_file(&mut self) {
+    /// Files an action applies to: the selected files of the current view, or the
+    /// highlighted one when nothing is selected.
+    fn get_target_files(&self) -> Vec<String> {
+        let selected: Vec<String> = self
+            .current_files()
+            .iter()
+            .filter(|f| f.selected != Selection::None)
+            .map(|f| f.path.clone())
+            .collect();
+        if !selected.is_empty() {
+            return selected;
+        }
         let highlighted_file = self.get_highlighted_file();
-        if !highlighted_file.is_empty() {
-            self.quit();
-            chezmoi::edit(highlighted_file);
+        if highlighted_file.is_empty() {
+            Vec::new()
+        } else {
+            vec![highlighted_file]
         }
     }
 
-    fn open_chezmoi_source(&mut self) {
-        self.quit();
-        chezmoi::open_source();
+    /// Whether the list shows `view`; otherwise tells that `action` is only available there.
+    fn require_view(&mut self, view: View, action: &str) -> bool {
+        if self.view == view {
+            return true;
+        }
+        self.error_message = Some(format!(
+            "{} is not available here, switch to the {} view",
+            action,
+            view.title()
+        ));
+        false
     }
 
-    fn quit(&mut self) {
-        self.running = false;
+    /// Shows the diff of the new highlight and prefetches its neighbours.
+    fn update_selected_diff(&mut self) {
+        self.diff_generation += 1;
+        self.show_highlighted_diff();
+        self.prefetch_neighbours();
     }
 
-    // --------------------------------------------------------
-    // UI
-    // --------------------------------------------------------
-
-    pub fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
-        terminal.clear()?;
-        self.running = true;
-        while self.running {
-            terminal.draw(|frame| self.draw(frame))?;
-            self.handle_crossterm_events()?;
+    /// Shows the highlighted diff from the cache, fetching it if it isn't there yet.
+    fn show_highlighted_diff(&mut self) {
+        self.diff_loading = false;
+        let highlighted_file = self.get_highlighted_file();
+        if highlighted_file.is_empty() {
+            self.rendered_diff = None;
+            self.set_diff(String::new(), PreviewKind::Diff);
+            return;
+        }
+        let preview_kind = self.preview_kind(&highlighted_file);
+        self.rendered_diff = self
+            .diff_cache
+            .rendered(&highlighted_file)
+            .filter(|_| preview_kind == PreviewKind::Diff)
+            .map(|rendered| ansi::to_lines(rendered));
+        if let Some(diff) = self.diff_cache.get(&highlighted_file, preview_kind) {
+            self.set_diff(diff.clone(), preview_kind);
+            return;
+        }
+        self.set_diff(String::new(), preview_kind);
+        self.diff_loading = true;
+        if !self.bulk_diff_pending || preview_kind != PreviewKind::Diff {
+            self.fetch_diff(&highlighted_file);
         }
-        Ok(())
     }
 
-    fn draw_popup(&mut self, frame: &mut Frame) {
-        let block = Block::default()
-            .title("Select an action")
-            .borders(Borders::ALL);
-        let area = frame.area();
+    /// Replaces the text of the diff pane. Hunk selection and scrolling survive only when
+    /// the text is unchanged.
+    fn set_diff(&mut self, diff: String, kind: PreviewKind) {
+        if diff == self.chezmoi_file_diff {
+            return;
+        }
+        (self.hunks, self.split_rows) = if kind == PreviewKind::Diff {
+            (hunks::parse(&diff), split_diff::rows(&diff))
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        self.chezmoi_file_diff = diff;
+        self.hunk_cursor = 0;
+        self.selected_hunks.clear();
+        self.diff_scroll = 0;
+        self.update_diff_matches();
+        if self.chezmoi_file_diff.is_empty() {
+            self.focus = Focus::List;
+        }
+    }
 
-        let popup_area = Rect {
-            x: (area.width - 60) / 2,
-            y: (area.height - 10) / 2,
-            width: 60,
-            height: 10,
+    fn prefetch_neighbours(&mut self) {
+        if self.bulk_diff_pending {
+            return;
+        }
+        let Some(highlighted) = self.list_state.selected() else {
+            return;
         };
+        let start = highlighted.saturating_sub(PREFETCH_RADIUS);
+        let end = (highlighted + PREFETCH_RADIUS + 1).min(self.rows.len());
+        let neighbours: Vec<String> = (start..end)
+            .filter(|&i| i != highlighted)
+            .filter_map(|i| match &self.rows[i] {
+                Row::File { index, .. } => Some(self.current_files()[*index].path.clone()),
+                Row::Dir { .. } => None,
+            })
+            .collect();
+        for path in neighbours {
+            self.fetch_diff(&path);
+        }
+    }
 
-        frame.render_widget(Clear, popup_area);
+    fn fetch_diff(&mut self, path: &str) {
+        let kind = self.preview_kind(path);
+        if self.diff_cache.is_missing(path, kind) {
+            self.diff_cache.mark_pending(path, kind);
+            self.worker.submit(Job::Diff {
+                path: path.to_string(),
+                generation: self.diff_generation,
+                epoch: self.diff_cache.epoch(),
+                kind,
+                external: self.config.diff.external,
+            });
+        }
+    }
 
-        // Update to use only the first part of the tuple (the display string)
-        let items: Vec<ListItem> = self
-            .popup_items
-            .iter()
-            .map(|(text, _)| ListItem::new(text.as_str()))
-            .collect();
+    /// Fetches every diff with a single `chezmoi diff` instead of one process per file.
+    fn fetch_bulk_diff(&mut self) {
+        self.bulk_diff_pending = true;
+        self.worker.submit(Job::BulkDiff {
+            epoch: self.diff_cache.epoch(),
+        });
+    }
 
-        let list = List::new(items)
-            .block(block)
-            .highlight_style(Style::default().bg(Color::DarkGray));
+    /// Switches between one `chezmoi diff` per file and a single one for every file. The
+    /// cache is dropped: bulk diffs have no external rendering, and per-file ones may be
+    /// older than the bulk diff.
+    fn toggle_bulk_diff(&mut self) {
+        self.bulk_diff = !self.bulk_diff;
+        self.diff_cache.invalidate();
+        self.bulk_diff_pending = false;
+        if self.bulk_diff {
+            self.fetch_bulk_diff();
+        }
+        self.update_selected_diff();
+    }
+
+    fn handle_worker_output(&mut self) {
+        while let Some(output) = self.worker.try_recv() {
+            match output {
+                JobOutput::Status(result) => match result {
+                    Ok(mut files) => {
+                        let highlighted_path = self.get_highlighted_path();
+                        // Files stay staged as long as they still have changes on that side
+                        let selections: HashMap<&str, &Selection> = self
+                            .files
+                            .iter()
+                            .map(|f| (f.path.as_str(), &f.selected))
+                            .collect();
+                        for file in &mut files {
+                            let still_changed = match selections.get(file.path.as_str()) {
+                                Some(Selection::Local) => {
+                                    file.local_status != FileStatus::Unchanged
+                                }
+                                Some(Selection::Source) => {
+                                    file.source_status != FileStatus::Unchanged
+                                }
+                                Some(Selection::None) | None => false,
+                            };
+                            if still_changed {
+                                file.selected = selections[file.path.as_str()].clone();
+                            }
+                        }
+                        self.files = files;
+                        if self.managed_loaded {
+                            let paths = self.managed_files.iter().map(|f| f.path.clone()).collect();
+                            self.set_managed_files(paths);
+                        }
+                        self.diff_cache.invalidate();
+                        self.bulk_diff_pending = false;
+                        if self.bulk_diff {
+                            self.fetch_bulk_diff();
+                        }
+                        self.rebuild_rows_at(highlighted_path);
+                        self.update_selected_diff();
+                        if std::mem::take(&mut self.apply_all_after_refresh) {
+                            self.apply_all_source_changes();
+                        }
+                    }
+                    Err(e) => {
+                        self.apply_all_after_refresh = false;
+                        self.error_message = Some(e.to_string());
+                    }
+                },
+                JobOutput::Diff {
+                    path,
+                    kind,
+                    epoch,
+                    result,
+                    rendered,
+                } => {
+                    if let Some(rendered) = rendered {
+                        self.diff_cache
+                            .insert_rendered(path.clone(), epoch, rendered);
+                    }
+                    let highlighted = path == self.get_highlighted_file();
+                    match result {
+                        // Skipped in favour of a newer request, unless it's still needed
+                        None => {
+                            self.diff_cache.clear_pending(&path, kind);
+                            if highlighted && self.diff_cache.get(&path, kind).is_none() {
+                                self.fetch_diff(&path);
+                            }
+                        }
+                        Some(Ok(diff)) => {
+                            if self.diff_cache.insert(path, kind, epoch, diff) && highlighted {
+                                self.show_highlighted_diff();
+                            }
+                        }
+                        Some(Err(e)) => {
+                            self.diff_cache.clear_pending(&path, kind);
+                            if highlighted {
+                                self.diff_loading = false;
+                                self.error_message = Some(e.to_string());
+                            }
+                        }
+                    }
+                }
+                JobOutput::BulkDiff { epoch, result } => {
+                    if epoch != self.diff_cache.epoch() {
+                        continue;
+                    }
+                    self.bulk_diff_pending = false;
+                    match result {
+                        Ok(mut diffs) => {
+                            // Entries without a section simply have nothing to show
+                            let paths: Vec<String> =
+                                self.files.iter().map(|f| f.path.clone()).collect();
+                            for path in paths {
+                                let diff = diffs.remove(&path).unwrap_or_default();
+                                self.diff_cache.insert(path, PreviewKind::Diff, epoch, diff);
+                            }
+                        }
+                        Err(e) => self.error_message = Some(e.to_string()),
+                    }
+                    self.show_highlighted_diff();
+                    self.prefetch_neighbours();
+                }
+                JobOutput::Managed(result) => match result {
+                    Ok(paths) => {
+                        let highlighted_path = self.get_highlighted_path();
+                        self.set_managed_files(paths);
+                        if self.view == View::Managed {
+                            self.rebuild_rows_at(highlighted_path);
+                            self.update_selected_diff();
+                        }
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::Unmanaged(result) => match result {
+                    Ok(paths) => {
+                        let highlighted_path = self.get_highlighted_path();
+                        self.set_unmanaged_files(paths);
+                        if self.view == View::Unmanaged {
+                            self.rebuild_rows_at(highlighted_path);
+                            self.update_selected_diff();
+                        }
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::DiscardPlan(result) => match result {
+                    Ok(plan) => {
+                        self.discard_plan = Some(plan);
+                        self.modal_scroll = 0;
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::Plan(result) => match result {
+                    Ok(plan) => {
+                        self.execution_plan = Some(plan);
+                        self.modal_scroll = 0;
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::Discard(result) => match result {
+                    Ok(_) => {
+                        self.error_message = None;
+                        self.refresh_status();
+                    }
+                    Err(e) => {
+                        self.diff_cache.invalidate();
+                        self.update_selected_diff();
+                        self.error_message = Some(e.to_string());
+                    }
+                },
+                JobOutput::PatchHunks(result) => match result {
+                    Ok(_) => {
+                        self.selected_hunks.clear();
+                        self.error_message = None;
+                        self.refresh_status();
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::GitStatus(result) => match result {
+                    Ok(status) => {
+                        let len = status.entries.len();
+                        match self.git_list_state.selected() {
+                            _ if len == 0 => self.git_list_state.select(None),
+                            Some(i) if i >= len => self.git_list_state.select(Some(len - 1)),
+                            Some(_) => {}
+                            None => self.git_list_state.select(Some(0)),
+                        }
+                        self.git_status = Some(status);
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::Git { command, result } => {
+                    match result {
+                        Ok(()) => self.error_message = None,
+                        Err(e) => {
+                            self.error_message = Some(e.to_string());
+                            // Give the message back so it can be fixed and committed again
+                            if let GitCommand::Commit(message) = &command {
+                                self.commit_message = Some(message.clone());
+                            }
+                        }
+                    }
+                    self.refresh_git_status();
+                    // Pulling may bring changes to apply
+                    if command == GitCommand::Pull {
+                        self.refresh_status();
+                    }
+                }
+                JobOutput::Update { apply_all, result } => match result {
+                    Ok(commits) => {
+                        self.error_message = None;
+                        if apply_all {
+                            self.apply_all_after_refresh = true;
+                        } else {
+                            self.update_commits = Some(commits);
+                            self.modal_scroll = 0;
+                        }
+                        self.refresh_status();
+                    }
+                    Err(e) => self.error_message = Some(e.to_string()),
+                },
+                JobOutput::Execute { re_add, apply } => {
+                    self.set_batch_results(BatchResults { re_add, apply });
+                    self.refresh_status();
+                }
+                JobOutput::Forget(result) | JobOutput::Chattr(result) | JobOutput::Add(result) => {
+                    match result {
+                        Ok(_) => {
+                            for file in self
+                                .files
+                                .iter_mut()
+                                .chain(&mut self.managed_files)
+                                .chain(&mut self.unmanaged_files)
+                            {
+                                file.selected = Selection::None;
+                            }
+                            self.error_message = None;
+                            self.refresh_status();
+                        }
+                        Err(e) => {
+                            // Some files may have been written before the failure
+                            self.diff_cache.invalidate();
+                            self.update_selected_diff();
+                            self.error_message = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        if !self.worker.is_busy() {
+            self.busy_label = None;
+        }
+    }
+
+    // --------------------------------------------------------
+    // Commands
+    // --------------------------------------------------------
+
+    fn toggle_selected_file(&mut self) {
+        self.clear_stage_results();
+        match self.get_highlighted_row().cloned() {
+            Some(Row::File { index, .. }) => {
+                let file = &mut self.current_files_mut()[index];
+                file.selected = file.next_selection();
+            }
+            // Select every file below the directory, or clear them if they all are
+            Some(Row::Dir { files, .. }) => {
+                let current_files = self.current_files_mut();
+                let all_selected = files
+                    .iter()
+                    .all(|&i| current_files[i].selected != Selection::None);
+                for i in files {
+                    let file = &mut current_files[i];
+                    if all_selected {
+                        file.selected = Selection::None;
+                    } else if file.selected == Selection::None {
+                        file.selected = file.first_selection();
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn switch_view(&mut self, view: View) {
+        if view == self.view {
+            return;
+        }
+        if let Some(path) = self.get_highlighted_path() {
+            self.view_highlights.insert(self.view, path);
+        }
+        self.view = view;
+        if view == View::Managed && !self.managed_loaded {
+            self.busy_label = Some("Loading managed files".to_string());
+            self.worker.submit(Job::Managed(self.filters.types.clone()));
+        }
+        if view == View::Unmanaged && !self.unmanaged_loaded {
+            self.load_unmanaged_files();
+        }
+        let highlighted_path = self.view_highlights.remove(&view);
+        self.list_state.select(None);
+        self.rebuild_rows_at(highlighted_path);
+        self.update_selected_diff();
+    }
+
+    fn load_unmanaged_files(&mut self) {
+        self.busy_label = Some("Loading unmanaged files".to_string());
+        self.worker
+            .submit(Job::Unmanaged(self.unmanaged_dir.clone()));
+    }
+
+    fn start_dir_input(&mut self) {
+        if self.require_view(View::Unmanaged, "Choosing a directory") {
+            self.dir_input = Some(self.unmanaged_dir.clone());
+        }
+    }
+
+    /// Browses the typed directory, accepting it with or without a leading `~/`.
+    fn submit_dir_input(&mut self) {
+        let Some(input) = self.dir_input.take() else {
+            return;
+        };
+        let dir = input.trim();
+        let dir = dir.strip_prefix(chezmoi::HOME).unwrap_or(dir);
+        self.unmanaged_dir = dir.trim_matches('/').to_string();
+        self.unmanaged_files.clear();
+        self.list_state.select(None);
+        self.rebuild_rows();
+        self.update_selected_diff();
+        self.load_unmanaged_files();
+    }
+
+    fn toggle_view_mode(&mut self) {
+        self.view_mode = match self.view_mode {
+            ViewMode::Flat => ViewMode::Tree,
+            ViewMode::Tree => ViewMode::Flat,
+        };
+        self.rebuild_rows();
+        self.update_selected_diff();
+    }
+
+    fn start_filter(&mut self) {
+        self.filter_input = true;
+    }
+
+    fn update_filter(&mut self, query: String) {
+        self.filter_query = query;
+        self.rebuild_rows();
+        self.update_selected_diff();
+    }
+
+    fn clear_filter(&mut self) {
+        self.filter_input = false;
+        self.update_filter(String::new());
+    }
+
+    fn show_filter_popup(&mut self) {
+        self.show_filter_popup = true;
+        self.filter_popup_state.select(Some(0));
+    }
+
+    fn toggle_highlighted_filter(&mut self) {
+        let items = Filters::items();
+        let Some(item) = self
+            .filter_popup_state
+            .selected()
+            .and_then(|i| items.get(i))
+        else {
+            return;
+        };
+        match *item {
+            FilterItem::Status(filter) => {
+                self.filters.toggle_status(filter);
+                self.rebuild_rows();
+                self.update_selected_diff();
+            }
+            // Entry types are filtered by chezmoi itself
+            FilterItem::Type(entry_type) => {
+                self.filters.cycle_type(entry_type);
+                self.managed_loaded = false;
+                self.refresh_status();
+            }
+        }
+    }
+
+    fn toggle_collapsed_dir(&mut self) {
+        if let Some(Row::Dir { path, .. }) = self.get_highlighted_row() {
+            let path = path.clone();
+            if !self.collapsed_dirs.remove(&path) {
+                self.collapsed_dirs.insert(path);
+            }
+            self.rebuild_rows();
+        }
+    }
+
+    /// Moves the highlighted file, or every file of the highlighted directory, to a staging
+    /// pane. Files without changes on that side are left where they are.
+    fn stage_highlighted(&mut self, stage: Stage) {
+        if !self.require_view(View::Status, "Staging") {
+            return;
+        }
+        let indices = match self.get_highlighted_row() {
+            Some(Row::File { index, .. }) => vec![*index],
+            Some(Row::Dir { files, .. }) => files.clone(),
+            None => return,
+        };
+        let mut skipped = Vec::new();
+        for i in indices {
+            let file = &mut self.files[i];
+            let status = match stage {
+                Stage::ReAdd => &file.local_status,
+                Stage::Apply => &file.source_status,
+            };
+            if *status == FileStatus::Unchanged
+                || (stage == Stage::ReAdd && *status == FileStatus::Run)
+            {
+                skipped.push(file.path.clone());
+            } else {
+                file.selected = stage.selection();
+            }
+        }
+        self.clear_stage_results();
+        if !skipped.is_empty() {
+            self.error_message = Some(format!(
+                "Nothing to {}: {}",
+                match stage {
+                    Stage::ReAdd => "re-add",
+                    Stage::Apply => "apply",
+                },
+                skipped.join(", ")
+            ));
+        }
+    }
+
+    fn clear_stage_results(&mut self) {
+        self.batch_results = None;
+    }
+
+    /// Prepares the plan of both staging panes; nothing runs until it is confirmed.
+    fn plan_staged_files(&mut self) {
+        let re_add = self.get_selected_local_files();
+        let apply = self.get_selected_source_files();
+        if re_add.is_empty() && apply.is_empty() {
+            return;
+        }
+        let scripts = self
+            .files
+            .iter()
+            .filter(|f| f.source_status == FileStatus::Run && apply.contains(&f.path))
+            .map(|f| f.path.clone())
+            .collect();
+        self.busy_label = Some("Preparing execution plan".to_string());
+        self.worker.submit(Job::Plan {
+            re_add,
+            apply,
+            scripts,
+        });
+    }
+
+    /// Runs both staging panes at once, re-adding before applying.
+    fn execute_plan(&mut self) {
+        let Some(plan) = self.execution_plan.take() else {
+            return;
+        };
+        let re_add: Vec<String> = plan.re_add.into_iter().map(|(path, _)| path).collect();
+        let apply = plan.apply;
+        self.clear_stage_results();
+        self.busy_label = Some(format!(
+            "Re-adding {} and applying {} file(s)",
+            re_add.len(),
+            apply.len()
+        ));
+        self.worker.submit(Job::Execute { re_add, apply });
+    }
+
+    /// Unstages the files that went through and shows the results modal; failed and
+    /// skipped files stay staged so they can be retried.
+    fn set_batch_results(&mut self, results: BatchResults) {
+        for stage in [Stage::ReAdd, Stage::Apply] {
+            for (path, result) in results.get(stage) {
+                if !matches!(result, Some(Ok(()))) {
+                    continue;
+                }
+                for file in self.files.iter_mut().chain(&mut self.managed_files) {
+                    if file.path == *path && file.selected == stage.selection() {
+                        file.selected = Selection::None;
+                    }
+                }
+            }
+        }
+        let failed = results.failed_count();
+        self.error_message =
+            (failed > 0).then(|| format!("{} file(s) failed, they are still staged", failed));
+        self.batch_results = Some(results);
+        self.show_batch_results = true;
+        self.modal_scroll = 0;
+    }
+
+    fn forget_files(&mut self, files: Vec<String>) {
+        if !files.is_empty() {
+            self.busy_label = Some(format!("Forgetting {} file(s)", files.len()));
+            self.worker.submit(Job::Forget(files));
+        }
+    }
+
+    fn chattr_files(&mut self, attributes: &str, files: Vec<String>) {
+        if !files.is_empty() {
+            self.busy_label = Some(format!("Changing attributes of {} file(s)", files.len()));
+            self.worker.submit(Job::Chattr {
+                attributes: attributes.to_string(),
+                paths: files,
+            });
+        }
+    }
+
+    fn add_files(&mut self, files: Vec<String>) {
+        if !files.is_empty() {
+            self.busy_label = Some(format!("Adding {} file(s)", files.len()));
+            self.worker.submit(Job::Add {
+                options: self.add_options.clone(),
+                paths: files,
+            });
+        }
+    }
+
+    fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::List if !self.chezmoi_file_diff.is_empty() => Focus::Diff,
+            _ => Focus::List,
+        };
+        // The pane switches between the external and the built-in rendering
+        if self.rendered_diff.is_some() {
+            self.diff_scroll = 0;
+        }
+    }
+
+    /// The external rendering is only shown while the list has focus: working on hunks
+    /// and searching needs the plain diff.
+    fn rendered_active(&self) -> bool {
+        self.rendered_diff.is_some() && self.focus == Focus::List && !self.split_view
+    }
+
+    /// First line of the shown file, when the diff or preview includes it, e.g. to find a
+    /// shebang.
+    fn first_file_line(&self, preview: bool) -> Option<&str> {
+        let mut lines = self.chezmoi_file_diff.lines();
+        if preview {
+            return lines.next();
+        }
+        lines.find(|line| {
+            line.starts_with("@@ -1,") || line.starts_with("@@ -1 ") || line.starts_with("@@ -0,0 ")
+        })?;
+        lines.next().and_then(|line| line.get(1..))
+    }
+
+    fn split_active(&self) -> bool {
+        self.split_view && !self.split_rows.is_empty()
+    }
+
+    /// Searchable text of every line of the diff pane.
+    fn diff_pane_lines(&self) -> Vec<String> {
+        if self.split_active() {
+            self.split_rows.iter().map(SplitRow::text).collect()
+        } else {
+            self.chezmoi_file_diff.lines().map(str::to_string).collect()
+        }
+    }
+
+    fn diff_pane_line_count(&self) -> usize {
+        if let Some(lines) = self
+            .rendered_diff
+            .as_ref()
+            .filter(|_| self.rendered_active())
+        {
+            lines.len()
+        } else if self.split_active() {
+            self.split_rows.len()
+        } else {
+            self.chezmoi_file_diff.lines().count()
+        }
+    }
+
+    /// Line of the diff pane showing line `diff_line` of the diff.
+    fn diff_pane_line(&self, diff_line: usize) -> usize {
+        if self.split_active() {
+            self.split_rows
+                .iter()
+                .position(|row| row.diff_line() >= diff_line)
+                .unwrap_or(self.split_rows.len())
+        } else {
+            diff_line
+        }
+    }
+
+    /// Switches between the unified and the side-by-side diff, keeping the same part of the
+    /// diff in view.
+    fn toggle_split_view(&mut self) {
+        let top = if self.split_active() {
+            self.split_rows
+                .get(self.diff_scroll as usize)
+                .map_or(0, SplitRow::diff_line)
+        } else {
+            self.diff_scroll as usize
+        };
+        self.split_view = !self.split_view;
+        self.update_diff_matches();
+        self.scroll_diff_to(self.diff_pane_line(top));
+    }
+
+    /// Scrolls the diff pane so that `line` is at its top, without scrolling past the end.
+    fn scroll_diff_to(&mut self, line: usize) {
+        let line_count = self.diff_pane_line_count();
+        let max_scroll = line_count.saturating_sub(self.diff_height as usize);
+        self.diff_scroll = line.min(max_scroll) as u16;
+    }
+
+    fn scroll_diff_by(&mut self, delta: isize) {
+        self.scroll_diff_to(self.diff_scroll.saturating_add_signed(delta as i16) as usize);
+    }
+
+    fn start_diff_search(&mut self) {
+        self.diff_search_input = true;
+        self.diff_search.clear();
+        self.diff_matches.clear();
+    }
+
+    /// Finds the lines containing the search, ignoring ASCII case.
+    fn update_diff_matches(&mut self) {
+        self.diff_match_cursor = None;
+        let query = self.diff_search.to_ascii_lowercase();
+        self.diff_matches = if query.is_empty() {
+            Vec::new()
+        } else {
+            self.diff_pane_lines()
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.to_ascii_lowercase().contains(&query))
+                .map(|(i, _)| i)
+                .collect()
+        };
+    }
+
+    /// Moves to the next (or previous) match, wrapping around. A new search starts from the
+    /// first match at or below the top of the pane.
+    fn jump_to_match(&mut self, forward: bool) {
+        let len = self.diff_matches.len();
+        if len == 0 {
+            if !self.diff_search.is_empty() {
+                self.error_message = Some(format!("Pattern not found: {}", self.diff_search));
+            }
+            return;
+        }
+        let next = match self.diff_match_cursor {
+            Some(cursor) if forward => (cursor + 1) % len,
+            Some(cursor) => (cursor + len - 1) % len,
+            None => self
+                .diff_matches
+                .iter()
+                .position(|&line| line >= self.diff_scroll as usize)
+                .unwrap_or(0),
+        };
+        self.diff_match_cursor = Some(next);
+        // Keep a little context above the match
+        self.scroll_diff_to(self.diff_matches[next].saturating_sub(2));
+    }
+
+    fn move_hunk_cursor(&mut self, forward: bool) {
+        if self.hunks.is_empty() {
+            return;
+        }
+        let len = self.hunks.len();
+        self.hunk_cursor = if forward {
+            (self.hunk_cursor + 1).min(len - 1)
+        } else {
+            self.hunk_cursor.saturating_sub(1)
+        };
+        // Keep a little context above the hunk
+        let line = self.diff_pane_line(self.hunks[self.hunk_cursor].start_line);
+        self.scroll_diff_to(line.saturating_sub(2));
+    }
+
+    fn toggle_selected_hunk(&mut self) {
+        if !self.selected_hunks.remove(&self.hunk_cursor) {
+            self.selected_hunks.insert(self.hunk_cursor);
+        }
+    }
+
+    /// The selected hunks, or the one under the cursor when none is selected.
+    fn get_target_hunks(&self) -> Vec<usize> {
+        if self.selected_hunks.is_empty() {
+            vec![self.hunk_cursor]
+        } else {
+            let mut hunks: Vec<usize> = self.selected_hunks.iter().copied().collect();
+            hunks.sort();
+            hunks
+        }
+    }
+
+    /// Re-adds the target hunks into the source file.
+    fn re_add_hunks(&mut self) {
+        let path = self.get_highlighted_file();
+        if path.is_empty() || self.hunks.is_empty() {
+            return;
+        }
+        let hunks = self.get_target_hunks();
+        self.busy_label = Some(format!("Re-adding {} hunk(s) of {}", hunks.len(), path));
+        self.worker.submit(Job::PatchHunks {
+            path,
+            diff: self.chezmoi_file_diff.clone(),
+            hunks,
+            side: PatchSide::Source,
+        });
+    }
+
+    /// Asks for confirmation before applying hunks to the target, which reverts its local
+    /// lines: applying and discarding local hunks are the same patch.
+    fn discard_hunks(&mut self) {
+        let path = self.get_highlighted_file();
+        if path.is_empty() || self.hunks.is_empty() {
+            return;
+        }
+        let plan = discard::plan_hunks(&path, &self.chezmoi_file_diff, self.get_target_hunks());
+        self.discard_plan = Some(plan);
+        self.modal_scroll = 0;
+    }
+
+    fn plan_discard(&mut self, path: String, side: DiscardSide) {
+        self.busy_label = Some(format!("Preparing to discard {} changes", side.label()));
+        self.worker.submit(Job::DiscardPlan { path, side });
+    }
+
+    fn confirm_discard(&mut self) {
+        if let Some(plan) = self.discard_plan.take() {
+            self.busy_label = Some(format!("Discarding {} changes", plan.side.label()));
+            self.worker.submit(Job::Discard(plan));
+        }
+    }
+
+    fn show_update_popup(&mut self) {
+        self.popup_title = "Update the source repository (chezmoi git pull)".to_string();
+        let items = vec![
+            (
+                "Update, then review incoming commits and files to apply".to_string(),
+                PopupAction::Update { apply_all: false },
+            ),
+            (
+                "Update and apply all".to_string(),
+                PopupAction::Update { apply_all: true },
+            ),
+            ("Cancel".to_string(), PopupAction::Cancel),
+        ];
+        self.show_popup(items);
+    }
+
+    fn update_source(&mut self, apply_all: bool) {
+        self.busy_label = Some("Pulling the source repository".to_string());
+        self.worker.submit(Job::Update { apply_all });
+    }
+
+    /// Stages every file with source changes for apply, then shows the execution plan.
+    fn apply_all_source_changes(&mut self) {
+        let mut staged = 0;
+        for file in &mut self.files {
+            if file.source_status != FileStatus::Unchanged {
+                file.selected = Selection::Source;
+                staged += 1;
+            }
+        }
+        if staged == 0 {
+            self.error_message = Some("Nothing to apply".to_string());
+            return;
+        }
+        self.clear_stage_results();
+        self.plan_staged_files();
+    }
+
+    fn open_git_panel(&mut self) {
+        self.show_git_panel = true;
+        self.error_message = None;
+        self.refresh_git_status();
+    }
+
+    fn refresh_git_status(&mut self) {
+        self.busy_label = Some("Loading git status".to_string());
+        self.worker.submit(Job::GitStatus);
+    }
+
+    fn run_git(&mut self, command: GitCommand) {
+        self.busy_label = Some(command.progress_label());
+        self.worker.submit(Job::Git(command));
+    }
+
+    fn get_highlighted_git_entry(&self) -> Option<&GitEntry> {
+        let status = self.git_status.as_ref()?;
+        status.entries.get(self.git_list_state.selected()?)
+    }
+
+    /// Stages the highlighted path, or unstages it when all its changes are staged.
+    fn toggle_staged_git_entry(&mut self) {
+        let Some(entry) = self.get_highlighted_git_entry() else {
+            return;
+        };
+        let paths = vec![entry.path.clone()];
+        if entry.has_unstaged_changes() {
+            self.run_git(GitCommand::Stage(paths));
+        } else {
+            self.run_git(GitCommand::Unstage(paths));
+        }
+    }
+
+    /// Stages every change, or unstages everything when it all is staged already.
+    fn toggle_staged_git_entries(&mut self) {
+        let Some(status) = &self.git_status else {
+            return;
+        };
+        let unstaged: Vec<String> = status
+            .entries
+            .iter()
+            .filter(|e| e.has_unstaged_changes())
+            .map(|e| e.path.clone())
+            .collect();
+        let staged: Vec<String> = status
+            .entries
+            .iter()
+            .filter(|e| e.is_staged())
+            .map(|e| e.path.clone())
+            .collect();
+        if !unstaged.is_empty() {
+            self.run_git(GitCommand::Stage(unstaged));
+        } else if !staged.is_empty() {
+            self.run_git(GitCommand::Unstage(staged));
+        }
+    }
+
+    fn start_commit(&mut self) {
+        let staged = self.git_status.as_ref().map_or(0, GitStatus::staged_count);
+        if staged == 0 {
+            self.error_message = Some("Nothing staged to commit".to_string());
+        } else {
+            self.error_message = None;
+            self.commit_message = Some(String::new());
+        }
+    }
+
+    fn submit_commit(&mut self) {
+        let Some(message) = self.commit_message.take() else {
+            return;
+        };
+        if message.trim().is_empty() {
+            self.error_message = Some("The commit message is empty".to_string());
+            self.commit_message = Some(message);
+            return;
+        }
+        self.run_git(GitCommand::Commit(message.trim_end().to_string()));
+    }
+
+    fn edit_highlighted_file(&mut self) {
+        let highlighted_file = self.get_highlighted_file();
+        if !highlighted_file.is_empty() {
+            self.interactive_command = Some(InteractiveCommand::Edit(highlighted_file));
+        }
+    }
+
+    fn merge_highlighted_file(&mut self) {
+        let highlighted_file = self.get_highlighted_file();
+        if !highlighted_file.is_empty() {
+            self.interactive_command = Some(InteractiveCommand::Merge(highlighted_file));
+        }
+    }
+
+    fn open_chezmoi_source(&mut self) {
+        self.interactive_command = Some(InteractiveCommand::OpenSource);
+    }
+
+    fn open_git_ui(&mut self) {
+        self.interactive_command = Some(InteractiveCommand::GitUi);
+    }
+
+    /// Hands the terminal over to an interactive command, then reloads everything it may
+    /// have changed. The highlight stays on the same path once the new status comes in.
+    fn run_interactive_command(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        command: InteractiveCommand,
+    ) -> Result<()> {
+        let backend = Arc::clone(&self.backend);
+        let git_ui = self.config.git_ui.command.clone();
+        let result = with_suspended_terminal(terminal, || match command {
+            InteractiveCommand::Edit(path) => backend.edit(path),
+            InteractiveCommand::OpenSource => backend.open_source(),
+            InteractiveCommand::Merge(path) => backend.merge(&path),
+            InteractiveCommand::GitUi => backend.open_git_ui(&git_ui),
+        })?;
+        self.error_message = result.err().map(|e| e.to_string());
+        self.diff_cache.invalidate();
+        self.refresh_status();
+        Ok(())
+    }
+
+    fn quit(&mut self) {
+        self.running = false;
+    }
+
+    // --------------------------------------------------------
+    // UI
+    // --------------------------------------------------------
+
+    pub fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
+        terminal.clear()?;
+        self.running = true;
+        while self.running {
+            terminal.draw(|frame| self.draw(frame))?;
+            self.handle_crossterm_events()?;
+            if let Some(command) = self.interactive_command.take() {
+                self.run_interactive_command(&mut terminal, command)?;
+            }
+            self.handle_worker_output();
+            if self.worker.is_busy() {
+                self.spinner_tick = self.spinner_tick.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_popup(&mut self, frame: &mut Frame) {
+        let block = Block::default()
+            .title(self.popup_title.as_str())
+            .borders(Borders::ALL);
+        let height = (self.popup_items.len() as u16 + 2).max(10);
+        let popup_area = centered_rect(frame.area(), 60, height);
+
+        frame.render_widget(Clear, popup_area);
+
+        // Update to use only the first part of the tuple (the display string)
+        let items: Vec<ListItem> = self
+            .popup_items
+            .iter()
+            .map(|(text, _)| ListItem::new(text.as_str()))
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_stateful_widget(list, popup_area, &mut self.popup_state);
+    }
+
+    fn draw_filter_popup(&mut self, frame: &mut Frame) {
+        let items: Vec<FilterItem> = Filters::items();
+        let block = Block::default()
+            .title("Filters (<space> toggle, +include/-exclude types)")
+            .borders(Borders::ALL);
+        let popup_area = centered_rect(frame.area(), 60, items.len() as u16 + 2);
+
+        frame.render_widget(Clear, popup_area);
+
+        let items: Vec<ListItem> = items
+            .into_iter()
+            .map(|item| ListItem::new(self.filters.item_label(item)))
+            .collect();
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_stateful_widget(list, popup_area, &mut self.filter_popup_state);
+    }
+
+    fn draw_add_popup(&mut self, frame: &mut Frame) {
+        let block = Block::default()
+            .title(format!(
+                "Add {} file(s) (<space> toggle option)",
+                self.add_targets.len()
+            ))
+            .borders(Borders::ALL);
+        let popup_area = centered_rect(frame.area(), 60, AddOption::ALL.len() as u16 + 4);
+
+        frame.render_widget(Clear, popup_area);
+
+        let mut items: Vec<ListItem> = AddOption::ALL
+            .iter()
+            .map(|option| {
+                let checkbox = if self.add_options.contains(option) {
+                    "[x]"
+                } else {
+                    "[ ]"
+                };
+                ListItem::new(format!(
+                    "{} {:<15} {}",
+                    checkbox,
+                    option.flag(),
+                    option.description()
+                ))
+            })
+            .collect();
+        items.push(ListItem::new("Add"));
+        items.push(ListItem::new("Cancel"));
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        frame.render_stateful_widget(list, popup_area, &mut self.add_popup_state);
+    }
+
+    fn draw_git_panel(&mut self, frame: &mut Frame) {
+        let area = frame.area();
+        let popup_area = centered_rect(area, area.width * 4 / 5, area.height * 4 / 5);
+        frame.render_widget(Clear, popup_area);
+
+        let mut title = vec![Span::from("Git").bold().blue()];
+        if let Some(status) = &self.git_status {
+            title.push(Span::raw(" "));
+            title.push(match &status.branch {
+                Some(branch) => Span::from(branch.clone()).cyan(),
+                None => Span::from("(detached HEAD)").yellow(),
+            });
+            match &status.upstream {
+                Some(upstream) => {
+                    title.push(Span::from(format!(" → {}", upstream)).dark_gray());
+                    title.push(Span::from(format!(" ↑{}", status.ahead)).green());
+                    title.push(Span::from(format!(" ↓{}", status.behind)).red());
+                }
+                None => title.push(Span::from(" (no upstream)").dark_gray()),
+            }
+        }
+        if let Some(label) = &self.busy_label {
+            let spinner = SPINNER[self.spinner_tick % SPINNER.len()];
+            title.push(Span::from(format!(" {} {}", spinner, label)).yellow());
+        }
+        let help = vec![
+            "<space>".blue().bold(),
+            " Stage/unstage".gray(),
+            " | ".dark_gray(),
+            "a".blue().bold(),
+            " All".gray(),
+            " | ".dark_gray(),
+            "c".blue().bold(),
+            " Commit".gray(),
+            " | ".dark_gray(),
+            "P".blue().bold(),
+            " Push".gray(),
+            " | ".dark_gray(),
+            "p".blue().bold(),
+            " Pull".gray(),
+            " | ".dark_gray(),
+            "r".blue().bold(),
+            " Refresh".gray(),
+            " | ".dark_gray(),
+            "<esc>".blue().bold(),
+            " Close".gray(),
+        ];
+        let block = Block::bordered()
+            .title(Line::from(title))
+            .title_bottom(Line::from(help));
+        let inner = block.inner(popup_area);
+        frame.render_widget(block, popup_area);
+
+        // Errors are shown inside the panel, which covers the bottom bar
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(1),
+                Constraint::Length(if self.error_message.is_some() { 2 } else { 0 }),
+            ])
+            .split(inner);
+        let entries = self
+            .git_status
+            .as_ref()
+            .map_or(&[][..], |status| status.entries.as_slice());
+        if entries.is_empty() {
+            let text = if self.git_status.is_some() {
+                "Nothing to commit, the source directory is clean"
+            } else {
+                "Loading…"
+            };
+            frame.render_widget(Paragraph::new(text.dark_gray()), chunks[0]);
+        } else {
+            let items: Vec<ListItem> = entries
+                .iter()
+                .map(|entry| {
+                    let code = |c: char| if c == '.' { ' ' } else { c };
+                    let (index, worktree) = if entry.is_untracked() {
+                        (Span::raw(" "), Span::from("?").red())
+                    } else {
+                        (
+                            Span::from(code(entry.index).to_string()).green(),
+                            Span::from(code(entry.worktree).to_string()).red(),
+                        )
+                    };
+                    ListItem::new(Line::from(vec![
+                        index,
+                        worktree,
+                        Span::raw(format!(" {}", entry.path)),
+                    ]))
+                })
+                .collect();
+            frame.render_stateful_widget(
+                List::new(items).highlight_style(Style::default().bg(Color::DarkGray)),
+                chunks[0],
+                &mut self.git_list_state,
+            );
+        }
+        if let Some(error) = &self.error_message {
+            frame.render_widget(
+                Paragraph::new(format!("Error: {}", error).red()).wrap(Wrap { trim: true }),
+                chunks[1],
+            );
+        }
+
+        if let Some(message) = &self.commit_message {
+            let staged = self.git_status.as_ref().map_or(0, GitStatus::staged_count);
+            let editor_area = centered_rect(area, area.width * 3 / 5, 10);
+            frame.render_widget(Clear, editor_area);
+            let mut lines: Vec<Line> = message.split('\n').map(Line::from).collect();
+            if let Some(last) = lines.last_mut() {
+                last.push_span("█".gray());
+            }
+            let help = vec![
+                "<enter>".blue().bold(),
+                " Commit".gray(),
+                " | ".dark_gray(),
+                "<alt-enter>".blue().bold(),
+                " New line".gray(),
+                " | ".dark_gray(),
+                "<esc>".blue().bold(),
+                " Cancel".gray(),
+            ];
+            let block = Block::bordered()
+                .title(format!("Commit message ({} staged)", staged).bold().blue())
+                .title_bottom(Line::from(help))
+                .border_style(Style::default().fg(Color::Green));
+            // Keep the end of long messages, where the cursor is, in view
+            let scroll = lines
+                .len()
+                .saturating_sub(editor_area.height.saturating_sub(2) as usize)
+                as u16;
+            frame.render_widget(
+                Paragraph::new(lines).block(block).scroll((scroll, 0)),
+                editor_area,
+            );
+        }
+    }
+
+    fn draw_update_modal(&self, frame: &mut Frame) {
+        let Some(commits) = &self.update_commits else {
+            return;
+        };
+        let mut lines = Vec::new();
+        if commits.is_empty() {
+            lines.push(Line::from("Already up to date".bold()));
+        } else {
+            lines.push(Line::from(
+                format!("{} incoming commit(s):", commits.len()).bold(),
+            ));
+        }
+        for commit in commits {
+            lines.push(Line::from(vec![
+                Span::from(format!("  {} ", commit.hash)).yellow(),
+                Span::raw(commit.subject.clone()),
+                Span::from(format!(" ({}, {})", commit.author, commit.date)).dark_gray(),
+            ]));
+        }
+        lines.push(Line::default());
+
+        let candidates: Vec<&FileItem> = self
+            .files
+            .iter()
+            .filter(|f| f.source_status != FileStatus::Unchanged)
+            .collect();
+        if candidates.is_empty() {
+            lines.push(Line::from("Nothing to apply".bold()));
+        } else {
+            lines.push(Line::from(
+                format!(
+                    "{} file(s) to apply, stage them in the status list with > or apply them all:",
+                    candidates.len()
+                )
+                .bold(),
+            ));
+        }
+        for file in candidates {
+            let (symbol, style) = status_symbol(&file.source_status);
+            let mut line = vec![
+                Span::raw("  "),
+                Span::styled(symbol, style),
+                Span::raw(format!(" {}", file.path)),
+            ];
+            if file.is_conflict() {
+                line.push(Span::from(" ⚠ conflict").red().bold());
+            }
+            lines.push(Line::from(line));
+        }
+
+        let title = Line::from("Update").bold().blue();
+        let help = vec![
+            "<enter>/<esc>".blue().bold(),
+            " Close".gray(),
+            " | ".dark_gray(),
+            "a".blue().bold(),
+            " Apply all".gray(),
+            " | ".dark_gray(),
+            "j/k".blue().bold(),
+            " Scroll".gray(),
+        ];
+        draw_modal(frame, title, help, lines, self.modal_scroll);
+    }
+
+    fn draw_discard_modal(&self, frame: &mut Frame) {
+        let Some(plan) = &self.discard_plan else {
+            return;
+        };
+        let lost_prefix = plan.side.lost_prefix();
+        let mut lines = vec![
+            Line::from(vec!["Runs: ".gray(), Span::raw(plan.method.description())]),
+            Line::from(format!("Lines marked {} will be lost:", lost_prefix).gray()),
+            Line::default(),
+        ];
+        if plan.diff.trim().is_empty() {
+            lines.push(Line::from("(no diff to show)".dark_gray()));
+        }
+        // Source changes undone with git are shown as the source file, e.g. a template
+        let syntax = match &plan.method {
+            DiscardMethod::GitCheckout { source_path } => Syntax::detect(source_path, None),
+            _ => Syntax::detect(&plan.path, None),
+        };
+        for (line, prefix) in hunk_line_prefixes(&plan.diff) {
+            let lost_side = prefix == Some(lost_prefix);
+            let kept_side = matches!(prefix, Some('+' | '-'));
+            if let (Some(_), Some(syntax)) = (prefix, &syntax) {
+                let line = syntax_line(line, syntax, true, "");
+                lines.push(if lost_side { line.bold() } else { line });
+                continue;
+            }
+            let line = Line::from(line.to_string());
+            lines.push(if lost_side {
+                line.red().bold()
+            } else if kept_side {
+                line.green()
+            } else {
+                line
+            });
+        }
+
+        let title = Line::from(format!(
+            "Discard {} changes to {}?",
+            plan.side.label(),
+            plan.path
+        ))
+        .bold()
+        .red();
+        draw_confirm_modal(frame, title, "Discard", lines, self.modal_scroll);
+    }
+
+    fn draw_results_modal(&self, frame: &mut Frame) {
+        let Some(results) = &self.batch_results else {
+            return;
+        };
+        let mut lines = Vec::new();
+        for (stage, verb) in [(Stage::ReAdd, "Re-add"), (Stage::Apply, "Apply")] {
+            let stage_results = results.get(stage);
+            if stage_results.is_empty() {
+                continue;
+            }
+            let (ok, failed, skipped) = count_results(stage_results);
+            lines.push(Line::from(
+                format!(
+                    "{}: {} succeeded, {} failed, {} skipped",
+                    verb, ok, failed, skipped
+                )
+                .bold(),
+            ));
+            for (path, result) in stage_results {
+                match result {
+                    Some(Ok(())) => lines.push(Line::from(format!("  ✓ {}", path).green())),
+                    Some(Err(e)) => {
+                        lines.push(Line::from(format!("  ✗ {}", path).red().bold()));
+                        for line in e.to_string().lines() {
+                            lines.push(Line::from(format!("      {}", line).red()));
+                        }
+                    }
+                    None => lines.push(Line::from(
+                        format!("  - {} (skipped because a re-add failed)", path).yellow(),
+                    )),
+                }
+            }
+            lines.push(Line::default());
+        }
+
+        let title = Line::from("Results").bold().blue();
+        let help = vec![
+            "<enter>/<esc>".blue().bold(),
+            " Close".gray(),
+            " | ".dark_gray(),
+            "j/k".blue().bold(),
+            " Scroll".gray(),
+        ];
+        draw_modal(frame, title, help, lines, self.modal_scroll);
+    }
+
+    fn draw_plan_modal(&self, frame: &mut Frame) {
+        let Some(plan) = &self.execution_plan else {
+            return;
+        };
+        let mut lines = Vec::new();
+        let conflicts: Vec<&FileItem> = self
+            .files
+            .iter()
+            .filter(|f| f.selected != Selection::None && f.is_conflict())
+            .collect();
+        if !conflicts.is_empty() {
+            lines.push(Line::from(
+                "Modified on both sides, consider merging them instead (m):"
+                    .red()
+                    .bold(),
+            ));
+            for file in conflicts {
+                let lost = match file.selected {
+                    Selection::Local => "source changes are lost",
+                    _ => "local changes are lost",
+                };
+                lines.push(Line::from(vec![
+                    Span::raw(format!("   {} ", file.path)),
+                    Span::from(lost).red(),
+                ]));
+            }
+            lines.push(Line::default());
+        }
+        if !plan.re_add.is_empty() {
+            lines.push(Line::from(
+                format!(
+                    "1. Re-add: source files overwritten ({})",
+                    plan.re_add.len()
+                )
+                .bold(),
+            ));
+            for (path, source_path) in &plan.re_add {
+                lines.push(Line::from(vec![
+                    Span::raw(format!("   {} ", path)),
+                    "→ ".dark_gray(),
+                    Span::from(source_path.clone()).yellow(),
+                ]));
+            }
+            lines.push(Line::default());
+        }
+        if !plan.apply.is_empty() {
+            lines.push(Line::from(
+                format!(
+                    "{}. Apply {} file(s)",
+                    if plan.re_add.is_empty() { 1 } else { 2 },
+                    plan.apply.len()
+                )
+                .bold(),
+            ));
+            if plan.targets.is_empty() {
+                lines.push(Line::from("   (no target changes)".dark_gray()));
+            }
+            for action in PlanAction::ALL {
+                let targets: Vec<&str> = plan.targets(action).collect();
+                if targets.is_empty() {
+                    continue;
+                }
+                lines.push(Line::from(format!(
+                    "   {} ({})",
+                    action.title(),
+                    targets.len()
+                )));
+                let style = match action {
+                    PlanAction::Write => Style::default().fg(Color::Yellow),
+                    PlanAction::Create => Style::default().fg(Color::Green),
+                    PlanAction::Delete => Style::default().fg(Color::Red),
+                    PlanAction::RunScript => Style::default().fg(Color::Magenta),
+                };
+                for target in targets {
+                    lines.push(Line::from(Span::styled(format!("     {}", target), style)));
+                }
+            }
+        }
+
+        let title = Line::from("Execution plan").bold().blue();
+        draw_confirm_modal(frame, title, "Execute", lines, self.modal_scroll);
+    }
+
+    fn draw_stage(&self, frame: &mut Frame, stage: Stage, area: Rect) {
+        let files: Vec<&FileItem> = self
+            .files
+            .iter()
+            .filter(|f| f.selected == stage.selection())
+            .collect();
+        let mut items = Vec::new();
+        let results = self.batch_results.as_ref().map(|r| r.get(stage));
+        if let Some(results) = results.filter(|r| !r.is_empty()) {
+            let (ok, failed, skipped) = count_results(results);
+            let mut summary = vec![Span::from(format!("✓ {} done ", ok)).green()];
+            if failed > 0 {
+                summary.push(Span::from(format!("✗ {} failed ", failed)).red());
+            }
+            if skipped > 0 {
+                summary.push(Span::from(format!("- {} skipped", skipped)).yellow());
+            }
+            items.push(ListItem::new(Line::from(summary)));
+        }
+        for file in &files {
+            let status = match stage {
+                Stage::ReAdd => &file.local_status,
+                Stage::Apply => &file.source_status,
+            };
+            let (symbol, style) = status_symbol(status);
+            items.push(ListItem::new(Line::from(vec![
+                Span::styled(symbol, style),
+                Span::raw(" "),
+                Span::raw(file.path.clone()),
+            ])));
+        }
+
+        let title = Line::from(vec![
+            Span::from(stage.title()).bold().blue(),
+            Span::from(format!(" ({})", files.len())).dark_gray(),
+        ]);
+        frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+    }
+
+    fn row_item(&self, row: &Row) -> ListItem<'static> {
+        let indent = "  ".repeat(row.depth());
+        let (selection_prefix, local_status, source_status, name, path_style, matches, conflict) =
+            match row {
+                Row::File { index, .. } => {
+                    let file = &self.current_files()[*index];
+                    let selection_prefix = match file.selected {
+                        Selection::None => " ",
+                        Selection::Local => "L",
+                        Selection::Source => "S",
+                    };
+                    let name = match self.view_mode {
+                        ViewMode::Flat => file.path.clone(),
+                        ViewMode::Tree => {
+                            file.path.rsplit('/').next().unwrap_or_default().to_string()
+                        }
+                    };
+                    // Matched positions relative to the displayed name
+                    let offset = file.path.len() - name.len();
+                    let matches: Vec<usize> = fuzzy::fuzzy_match(&self.filter_query, &file.path)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|p| p.checked_sub(offset))
+                        .collect();
+                    let path_style = if file.is_script() {
+                        Style::default().fg(Color::Magenta)
+                    } else {
+                        Style::default()
+                    };
+                    (
+                        selection_prefix,
+                        file.local_status.clone(),
+                        file.source_status.clone(),
+                        name,
+                        path_style,
+                        matches,
+                        file.is_conflict(),
+                    )
+                }
+                Row::Dir {
+                    name,
+                    collapsed,
+                    files,
+                    ..
+                } => {
+                    let files: Vec<&FileItem> =
+                        files.iter().map(|&i| &self.current_files()[i]).collect();
+                    (
+                        tree::aggregate_selection(files.iter().map(|f| &f.selected)),
+                        tree::aggregate_status(files.iter().map(|f| &f.local_status)),
+                        tree::aggregate_status(files.iter().map(|f| &f.source_status)),
+                        format!("{} {}/", if *collapsed { "▶" } else { "▼" }, name),
+                        Style::default().fg(Color::Blue),
+                        Vec::new(),
+                        // Only flagged while collapsed, the files say it otherwise
+                        *collapsed && files.iter().any(|f| f.is_conflict()),
+                    )
+                }
+            };
+        let (local_symbol, local_style) = status_symbol(&local_status);
+        let (source_symbol, source_style) = status_symbol(&source_status);
+
+        let mut spans = vec![
+            Span::styled(
+                format!("{} ", selection_prefix),
+                if selection_prefix == " " {
+                    Style::default()
+                } else {
+                    Style::default().fg(Color::Green)
+                },
+            ),
+            Span::styled(local_symbol, local_style),
+            Span::styled(source_symbol, source_style),
+            Span::raw(" "),
+            Span::raw(indent),
+        ];
+        let match_style = path_style.fg(Color::Yellow).bold();
+        for (i, c) in name.char_indices() {
+            let style = if matches.contains(&i) {
+                match_style
+            } else {
+                path_style
+            };
+            spans.push(Span::styled(c.to_string(), style));
+        }
+        if conflict {
+            spans.push(Span::from(" ⚠ conflict").red().bold());
+        }
+
+        ListItem::new(Line::from(spans))
+    }
+
+    /// Keys of the help bar: those of the diff pane when it has focus, otherwise those of the
+    /// current view and the global ones, so that they fit on the bar.
+    fn help_keys(&self) -> Vec<(&'static str, &'static str)> {
+        if self.focus == Focus::Diff {
+            return vec![
+                ("j/k", "Scroll"),
+                ("^d/^u", "Half page"),
+                ("g/G", "Top/bottom"),
+                ("/", "Search"),
+                ("n/N", "Next/previous match"),
+                ("]/[", "Next/previous hunk"),
+                ("<space>", "Select hunk"),
+                ("R", "Re-add hunks"),
+                ("a", "Apply hunks to target"),
+                ("x", "Discard local lines"),
+                ("v", "Toggle split diff"),
+                ("<tab>/<esc>", "Back to the list"),
+            ];
+        }
+        let mut keys = vec![("q/Esc", "Quit"), ("↑/k ↓/j", "Move")];
+        keys.extend_from_slice(match self.view {
+            View::Status => &[
+                ("<space>", "Cycle staging"),
+                ("</>", "Stage for re-add/apply"),
+                ("A", "Execute staged"),
+                ("e", "Edit in source"),
+                ("m", "Merge"),
+                ("D", "Discard changes"),
+                ("B", "Toggle bulk diff"),
+            ][..],
+            View::Managed => &[
+                ("<space>", "Select file(s)"),
+                ("e", "Edit in source"),
+                ("D", "Discard changes"),
+                ("F", "Forget"),
+                ("c", "Change attributes"),
+            ],
+            View::Unmanaged => &[
+                ("<space>", "Select file(s)"),
+                ("a", "Add files"),
+                ("d", "Choose directory"),
+            ],
+        });
+        keys.extend_from_slice(&[
+            ("1/2/3", "Views"),
+            ("t", "Toggle tree view"),
+            ("/", "Filter"),
+            ("f", "Status/type filters"),
+            ("v", "Toggle split diff"),
+            ("S", "Open source"),
+            ("G", "Git panel"),
+            ("L", "lazygit"),
+            ("U", "Update"),
+            ("<tab>", "Focus diff"),
+        ]);
+        keys
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let main_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(3)])
+            .split(frame.area());
+
+        let content_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(main_chunks[0]);
+
+        let spinner = SPINNER[self.spinner_tick % SPINNER.len()];
+        let mut status_title = Vec::new();
+        for (i, view) in View::ALL.iter().enumerate() {
+            if i > 0 {
+                status_title.push(Span::from(" | ").dark_gray());
+            }
+            let label = format!("[{}] {}", i + 1, view.title());
+            status_title.push(if *view == self.view {
+                Span::from(label).bold().blue()
+            } else {
+                Span::from(label).dark_gray()
+            });
+        }
+        if self.view == View::Unmanaged {
+            status_title
+                .push(Span::from(format!(" {}{}", chezmoi::HOME, self.unmanaged_dir)).cyan());
+        }
+        let filter_summary = self.filters.summary();
+        if !filter_summary.is_empty() {
+            status_title.push(Span::from(format!(" {}", filter_summary)).magenta());
+        }
+        if !self.filter_query.is_empty() {
+            status_title.push(Span::from(format!(" /{}", self.filter_query)).yellow());
+            status_title.push(
+                Span::from(format!(
+                    " ({}/{})",
+                    self.visible_file_count(),
+                    self.current_files().len()
+                ))
+                .dark_gray(),
+            );
+        }
+        let conflicts = self
+            .current_files()
+            .iter()
+            .filter(|f| f.is_conflict())
+            .count();
+        if conflicts > 0 {
+            status_title.push(
+                Span::from(format!(" {} conflict(s)", conflicts))
+                    .red()
+                    .bold(),
+            );
+        }
+        if let Some(label) = &self.busy_label {
+            status_title.push(Span::raw(" "));
+            status_title.push(Span::from(format!("{} {}", spinner, label)).yellow());
+        }
+        let status_title = Line::from(status_title).centered();
+        let preview_kind = self.preview_kind(&self.get_highlighted_file());
+        let diff_title = match preview_kind {
+            PreviewKind::Diff => "Chezmoi Diff",
+            PreviewKind::Contents | PreviewKind::Target => "Chezmoi Preview",
+        };
+        let mut diff_title = vec![Span::from(diff_title).bold().blue()];
+        if !self.hunks.is_empty() {
+            diff_title.push(
+                Span::from(format!(
+                    " hunk {}/{}",
+                    self.hunk_cursor + 1,
+                    self.hunks.len()
+                ))
+                .dark_gray(),
+            );
+            if !self.selected_hunks.is_empty() {
+                diff_title
+                    .push(Span::from(format!(" ({} selected)", self.selected_hunks.len())).green());
+            }
+        }
+        let line_count = self.diff_pane_line_count();
+        if self.rendered_active() {
+            diff_title.push(Span::from(" external").magenta());
+        } else if self.split_active() {
+            diff_title.push(Span::from(" split").magenta());
+        }
+        // Borders take two lines of the pane
+        self.diff_height = content_chunks[1].height.saturating_sub(2);
+        if line_count > 0 {
+            let first = self.diff_scroll as usize + 1;
+            let last = (self.diff_scroll as usize + self.diff_height as usize).min(line_count);
+            diff_title.push(Span::from(format!(" {}-{}/{}", first, last, line_count)).dark_gray());
+        }
+        if !self.diff_search.is_empty() && !self.diff_search_input {
+            let matches = match self.diff_match_cursor {
+                Some(cursor) => format!("{}/{}", cursor + 1, self.diff_matches.len()),
+                None => self.diff_matches.len().to_string(),
+            };
+            diff_title
+                .push(Span::from(format!(" /{} ({} matches)", self.diff_search, matches)).yellow());
+        }
+        if self.diff_loading {
+            diff_title.push(Span::raw(" "));
+            diff_title.push(Span::from(spinner).yellow());
+        }
+        let diff_title = Line::from(diff_title).centered();
 
-        frame.render_stateful_widget(list, popup_area, &mut self.popup_state);
-    }
+        // Status list rendering with selection indicators
+        let items: Vec<ListItem> = self.rows.iter().map(|row| self.row_item(row)).collect();
 
-    fn draw(&mut self, frame: &mut Frame) {
-        let main_chunks = Layout::default()
+        let list_chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Min(3), Constraint::Length(3)])
-            .split(frame.area());
-
-        let content_chunks = Layout::default()
+            .constraints([Constraint::Min(3), Constraint::Length(STAGE_HEIGHT)])
+            .split(content_chunks[0]);
+        let stage_chunks = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(main_chunks[0]);
-
-        let status_title = Line::from("Chezmoi Status").bold().blue().centered();
-        let diff_title = Line::from("Chezmoi Diff").bold().blue().centered();
-
-        // Status list rendering with selection indicators
-        let items: Vec<ListItem> = self
-            .files
-            .iter()
-            .map(|file| {
-                let (local_symbol, local_style) = match file.local_status {
-                    FileStatus::Added => ("A", Style::default().fg(Color::Green)),
-                    FileStatus::Modified => ("M", Style::default().fg(Color::Yellow)),
-                    FileStatus::Deleted => ("D", Style::default().fg(Color::Red)),
-                    FileStatus::Untracked => ("?", Style::default().fg(Color::Red)),
-                    FileStatus::Unchanged => (" ", Style::default()),
-                };
-
-                let (source_symbol, source_style) = match file.source_status {
-                    FileStatus::Added => ("A", Style::default().fg(Color::Green)),
-                    FileStatus::Modified => ("M", Style::default().fg(Color::Yellow)),
-                    FileStatus::Deleted => ("D", Style::default().fg(Color::Red)),
-                    FileStatus::Untracked => ("?", Style::default().fg(Color::Red)),
-                    FileStatus::Unchanged => (" ", Style::default()),
-                };
-
-                let selection_prefix = match file.selected {
-                    Selection::None => " ",
-                    Selection::Local => "L",
-                    Selection::Source => "S",
-                };
-                ListItem::new(Line::from(vec![
-                    Span::styled(
-                        format!("{} ", selection_prefix),
-                        if file.selected == Selection::None {
-                            Style::default()
-                        } else {
-                            Style::default().fg(Color::Green)
-                        },
-                    ),
-                    Span::styled(local_symbol, local_style),
-                    Span::styled(source_symbol, source_style),
-                    Span::raw(" "),
-                    Span::raw(&file.path),
-                ]))
-            })
-            .collect();
+            .split(list_chunks[1]);
 
         frame.render_stateful_widget(
             List::new(items)
                 .block(Block::bordered().title(status_title))
                 .highlight_style(Style::default().bg(Color::DarkGray)),
-            content_chunks[0],
+            list_chunks[0],
             &mut self.list_state,
         );
+        self.draw_stage(frame, Stage::ReAdd, stage_chunks[0]);
+        self.draw_stage(frame, Stage::Apply, stage_chunks[1]);
 
         // Coloured diff rendering
-        let diff_lines: Vec<Line> = self
-            .chezmoi_file_diff
-            .lines()
-            .map(|line| {
-                if line.starts_with('+') {
-                    Line::from(line.to_string()).green()
-                } else if line.starts_with('-') {
-                    Line::from(line.to_string()).red()
-                } else if line.starts_with("@@") {
-                    Line::from(line.to_string()).cyan()
-                } else {
-                    Line::from(line.to_string())
-                }
-            })
-            .collect();
+        let show_contents = preview_kind != PreviewKind::Diff;
+        let diff_focused = self.focus == Focus::Diff;
+        let syntax = Syntax::detect(
+            &self.get_highlighted_file(),
+            self.first_file_line(show_contents),
+        );
+        // Gutter: the hunk under the cursor and the selected ones
+        let with_gutter = |diff_line: usize, line: Line<'static>| {
+            if !diff_focused {
+                return line;
+            }
+            let hunk = self
+                .hunks
+                .iter()
+                .position(|h| (h.start_line..h.start_line + h.line_count).contains(&diff_line));
+            let cursor = if hunk == Some(self.hunk_cursor) {
+                Span::from("▌").yellow()
+            } else {
+                Span::raw(" ")
+            };
+            let selected = match hunk {
+                Some(h) if self.selected_hunks.contains(&h) => Span::from("● ").green(),
+                _ => Span::raw("  "),
+            };
+            let mut spans = vec![cursor, selected];
+            spans.extend(line.spans.into_iter().map(|span| {
+                let style = line.style.patch(span.style);
+                span.style(style)
+            }));
+            Line::from(spans)
+        };
+        let diff_lines: Vec<Line> = if let Some(lines) = self
+            .rendered_diff
+            .as_ref()
+            .filter(|_| self.rendered_active())
+        {
+            lines.clone()
+        } else if self.split_active() {
+            // Inside the borders and the gutter
+            let width = content_chunks[1]
+                .width
+                .saturating_sub(if diff_focused { 5 } else { 2 }) as usize;
+            let number_width = self
+                .split_rows
+                .iter()
+                .filter_map(|row| match row {
+                    SplitRow::Pair { old, new, .. } => {
+                        Some(old.iter().chain(new).map(|l| l.number).max()?)
+                    }
+                    SplitRow::Header { .. } => None,
+                })
+                .max()
+                .unwrap_or_default()
+                .to_string()
+                .len();
+            self.split_rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let query = if self.diff_matches.binary_search(&i).is_ok() {
+                        self.diff_search.as_str()
+                    } else {
+                        ""
+                    };
+                    with_gutter(
+                        row.diff_line(),
+                        split_row_line(row, width, number_width, syntax.as_ref(), query),
+                    )
+                })
+                .collect()
+        } else {
+            self.chezmoi_file_diff
+                .lines()
+                .enumerate()
+                .map(|(i, line)| {
+                    let matched = self.diff_matches.binary_search(&i).is_ok();
+                    if let Some(syntax) = &syntax {
+                        let query = if matched {
+                            self.diff_search.as_str()
+                        } else {
+                            ""
+                        };
+                        let in_hunk = self
+                            .hunks
+                            .iter()
+                            .any(|h| (h.start_line + 1..h.start_line + h.line_count).contains(&i));
+                        if show_contents || in_hunk {
+                            return with_gutter(i, syntax_line(line, syntax, in_hunk, query));
+                        }
+                    }
+                    let text = if matched {
+                        highlight_matches(line, &self.diff_search)
+                    } else {
+                        Line::from(line.to_string())
+                    };
+                    let line = if show_contents {
+                        text
+                    } else if line.starts_with('+') {
+                        text.green()
+                    } else if line.starts_with('-') {
+                        text.red()
+                    } else if line.starts_with("@@") {
+                        text.cyan()
+                    } else {
+                        text
+                    };
+                    with_gutter(i, line)
+                })
+                .collect()
+        };
 
+        let diff_border = if diff_focused {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default()
+        };
         frame.render_widget(
-            Paragraph::new(diff_lines).block(Block::bordered().title(diff_title)),
+            Paragraph::new(diff_lines)
+                .block(
+                    Block::bordered()
+                        .title(diff_title)
+                        .border_style(diff_border),
+                )
+                .scroll((self.diff_scroll, 0)),
             content_chunks[1],
         );
+        if line_count > self.diff_height as usize {
+            let mut scrollbar_state = ScrollbarState::new(line_count)
+                .viewport_content_length(self.diff_height as usize)
+                .position(self.diff_scroll as usize);
+            frame.render_stateful_widget(
+                Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                    .begin_symbol(None)
+                    .end_symbol(None),
+                content_chunks[1].inner(ratatui::layout::Margin {
+                    vertical: 1,
+                    horizontal: 0,
+                }),
+                &mut scrollbar_state,
+            );
+        }
 
         // Add help/Error message section at the bottom
-        if let Some(error) = &self.error_message {
+        if self.diff_search_input {
+            let search_text = Line::from(vec![
+                "/".yellow().bold(),
+                Span::raw(&self.diff_search),
+                "█".gray(),
+                "   ".into(),
+                "<enter>".blue().bold(),
+                " Search diff".gray(),
+                " | ".dark_gray(),
+                "<esc>".blue().bold(),
+                " Cancel".gray(),
+            ]);
+
+            frame.render_widget(Paragraph::new(search_text), main_chunks[1]);
+        } else if self.filter_input {
+            let filter_text = Line::from(vec![
+                "/".yellow().bold(),
+                Span::raw(&self.filter_query),
+                "█".gray(),
+                "   ".into(),
+                "<enter>".blue().bold(),
+                " Keep filter".gray(),
+                " | ".dark_gray(),
+                "<esc>".blue().bold(),
+                " Clear filter".gray(),
+            ]);
+
+            frame.render_widget(Paragraph::new(filter_text), main_chunks[1]);
+        } else if let Some(dir) = &self.dir_input {
+            let dir_text = Line::from(vec![
+                "Directory: ".yellow().bold(),
+                Span::raw(chezmoi::HOME),
+                Span::raw(dir),
+                "█".gray(),
+                "   ".into(),
+                "<enter>".blue().bold(),
+                " Browse".gray(),
+                " | ".dark_gray(),
+                "<esc>".blue().bold(),
+                " Cancel".gray(),
+            ]);
+
+            frame.render_widget(Paragraph::new(dir_text), main_chunks[1]);
+        } else if let Some(error) = &self.error_message {
             let error_text = Line::from(vec![
                 Span::styled("Error: ", Style::default().fg(Color::Red)),
                 Span::raw(error),
@@ -348,31 +2959,19 @@ impl App {
                 main_chunks[1], // Use the bottom section where help text is
             );
         } else {
-            let help_text = vec![
-                "q/Esc".blue().bold(),
-                " Quit".gray(),
-                " | ".dark_gray(),
-                "↑/k".blue().bold(),
-                " Up".gray(),
-                " | ".dark_gray(),
-                "↓/j".blue().bold(),
-                " Down".gray(),
-                " | ".dark_gray(),
-                "<space>".blue().bold(),
-                " Select file(s)".gray(),
-                " | ".dark_gray(),
-                "E".blue().bold(),
-                " Edit highlighted file in source".gray(),
-                " | ".dark_gray(),
-                "A".blue().bold(),
-                " Apply/Re-add selected files".gray(),
-                " | ".dark_gray(),
-                "S".blue().bold(),
-                " Open chezmoi source".gray(),
-            ];
+            let mut help_text = Vec::new();
+            for (i, (key, label)) in self.help_keys().into_iter().enumerate() {
+                if i > 0 {
+                    help_text.push(" | ".dark_gray());
+                }
+                help_text.push(key.blue().bold());
+                help_text.push(format!(" {}", label).gray());
+            }
 
             frame.render_widget(
-                Paragraph::new(Line::from(help_text)).alignment(ratatui::layout::Alignment::Left),
+                Paragraph::new(Line::from(help_text))
+                    .alignment(ratatui::layout::Alignment::Left)
+                    .wrap(Wrap { trim: true }),
                 main_chunks[1],
             );
         }
@@ -380,6 +2979,27 @@ impl App {
         if self.show_popup {
             self.draw_popup(frame);
         }
+        if self.show_filter_popup {
+            self.draw_filter_popup(frame);
+        }
+        if self.show_add_popup {
+            self.draw_add_popup(frame);
+        }
+        if self.discard_plan.is_some() {
+            self.draw_discard_modal(frame);
+        }
+        if self.execution_plan.is_some() {
+            self.draw_plan_modal(frame);
+        }
+        if self.show_batch_results {
+            self.draw_results_modal(frame);
+        }
+        if self.show_git_panel {
+            self.draw_git_panel(frame);
+        }
+        if self.update_commits.is_some() {
+            self.draw_update_modal(frame);
+        }
     }
 
     pub fn show_popup(&mut self, items: Vec<(String, PopupAction)>) {
@@ -388,22 +3008,128 @@ impl App {
         self.popup_state.select(Some(0));
     }
 
-    pub fn show_action_popup(&mut self) {
-        self.popup_items = vec![
-            ("Apply selected files".to_string(), PopupAction::Apply),
-            ("Re-add selected files".to_string(), PopupAction::ReAdd),
+    fn show_forget_popup(&mut self) {
+        if !self.require_view(View::Managed, "Forgetting files") {
+            return;
+        }
+        let targets = self.get_target_files();
+        if targets.is_empty() {
+            return;
+        }
+        self.popup_title = format!("Forget {} file(s)? The targets are kept", targets.len());
+        let items = vec![
+            (
+                format!("Forget {}", targets.join(", ")),
+                PopupAction::Forget,
+            ),
             ("Cancel".to_string(), PopupAction::Cancel),
         ];
-        self.show_popup = true;
-        self.popup_state.select(Some(0));
+        self.popup_targets = targets;
+        self.show_popup(items);
+        // Default to the harmless choice
+        self.popup_state.select(Some(self.popup_items.len() - 1));
+    }
+
+    fn show_chattr_popup(&mut self) {
+        if !self.require_view(View::Managed, "Changing attributes") {
+            return;
+        }
+        let targets = self.get_target_files();
+        if targets.is_empty() {
+            return;
+        }
+        self.popup_title = format!("Change attributes of {} file(s)", targets.len());
+        let mut items: Vec<(String, PopupAction)> = CHATTR_ATTRIBUTES
+            .iter()
+            .map(|attribute| (attribute.to_string(), PopupAction::Chattr(attribute)))
+            .collect();
+        items.push(("Cancel".to_string(), PopupAction::Cancel));
+        self.popup_targets = targets;
+        self.show_popup(items);
+    }
+
+    fn show_add_popup(&mut self) {
+        if !self.require_view(View::Unmanaged, "Adding files") {
+            return;
+        }
+        let targets = self.get_target_files();
+        if targets.is_empty() {
+            return;
+        }
+        self.add_targets = targets;
+        self.show_add_popup = true;
+        self.add_popup_state.select(Some(AddOption::ALL.len()));
+    }
+
+    /// Toggles the highlighted option of the add popup, or runs/cancels the add.
+    fn handle_add_popup_selection(&mut self, toggle_only: bool) {
+        let Some(i) = self.add_popup_state.selected() else {
+            return;
+        };
+        if let Some(option) = AddOption::ALL.get(i) {
+            if let Some(position) = self.add_options.iter().position(|o| o == option) {
+                self.add_options.remove(position);
+            } else {
+                self.add_options.push(*option);
+            }
+        } else if !toggle_only {
+            self.show_add_popup = false;
+            let targets = std::mem::take(&mut self.add_targets);
+            if i == AddOption::ALL.len() {
+                self.add_files(targets);
+            }
+        }
+    }
+
+    fn show_discard_popup(&mut self) {
+        let Some(Row::File { index, .. }) = self.get_highlighted_row().cloned() else {
+            return;
+        };
+        if self.view == View::Unmanaged {
+            return;
+        }
+        let file = &self.current_files()[index];
+        let path = file.path.clone();
+        let mut items = Vec::new();
+        if file.local_status != FileStatus::Unchanged && !file.is_script() {
+            items.push((
+                "Discard local changes (restore the target from source)".to_string(),
+                PopupAction::Discard(DiscardSide::Local),
+            ));
+        }
+        if file.source_status != FileStatus::Unchanged && !file.is_script() {
+            items.push((
+                "Discard source changes (reset the source file)".to_string(),
+                PopupAction::Discard(DiscardSide::Source),
+            ));
+        }
+        if items.is_empty() {
+            return;
+        }
+        items.push(("Cancel".to_string(), PopupAction::Cancel));
+        self.popup_title = format!("Discard changes to {}", path);
+        self.popup_targets = vec![path];
+        self.show_popup(items);
     }
 
     fn handle_popup_selection(&mut self) {
         if let Some(i) = self.popup_state.selected() {
-            if let Some((_, action)) = self.popup_items.get(i) {
+            if let Some((_, action)) = self.popup_items.get(i).cloned() {
                 match action {
-                    PopupAction::Apply => self.apply_selected_files(),
-                    PopupAction::ReAdd => self.re_add_selected_files(),
+                    PopupAction::Forget => {
+                        let targets = std::mem::take(&mut self.popup_targets);
+                        self.forget_files(targets);
+                    }
+                    PopupAction::Chattr(attributes) => {
+                        let targets = std::mem::take(&mut self.popup_targets);
+                        self.chattr_files(attributes, targets);
+                    }
+                    PopupAction::Discard(side) => {
+                        if let Some(path) = std::mem::take(&mut self.popup_targets).pop() {
+                            self.plan_discard(path, side);
+                        }
+                    }
+                    PopupAction::Update { apply_all } => self.update_source(apply_all),
                     PopupAction::Cancel => self.show_popup = false,
                 }
             }
@@ -415,6 +3141,9 @@ impl App {
     // --------------------------------------------------------
 
     fn handle_crossterm_events(&mut self) -> Result<()> {
+        if !event::poll(TICK_RATE)? {
+            return Ok(());
+        }
         match event::read()? {
             Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key_event(key),
             Event::Mouse(_) => {}
@@ -425,14 +3154,95 @@ impl App {
     }
 
     fn on_key_event(&mut self, key: KeyEvent) {
-        if self.show_popup {
+        if self.show_batch_results {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') => {
+                    self.show_batch_results = false
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.modal_scroll = self.modal_scroll.saturating_add(1)
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.modal_scroll = self.modal_scroll.saturating_sub(1)
+                }
+                _ => {}
+            }
+        } else if self.update_commits.is_some() {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') => self.update_commits = None,
+                KeyCode::Char('a') => {
+                    self.update_commits = None;
+                    self.apply_all_source_changes();
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.modal_scroll = self.modal_scroll.saturating_add(1)
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.modal_scroll = self.modal_scroll.saturating_sub(1)
+                }
+                _ => {}
+            }
+        } else if self.discard_plan.is_some() || self.execution_plan.is_some() {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    if self.discard_plan.is_some() {
+                        self.confirm_discard();
+                    } else {
+                        self.execute_plan();
+                    }
+                }
+                KeyCode::Char('n') | KeyCode::Char('q') | KeyCode::Esc => {
+                    self.discard_plan = None;
+                    self.execution_plan = None;
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.modal_scroll = self.modal_scroll.saturating_add(1)
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.modal_scroll = self.modal_scroll.saturating_sub(1)
+                }
+                _ => {}
+            }
+        } else if let Some(message) = &mut self.commit_message {
+            match key.code {
+                KeyCode::Esc => self.commit_message = None,
+                KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) => message.push('\n'),
+                KeyCode::Enter => self.submit_commit(),
+                KeyCode::Backspace => {
+                    message.pop();
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    message.push(c)
+                }
+                _ => {}
+            }
+        } else if self.show_git_panel {
+            let len = self.git_status.as_ref().map_or(0, |s| s.entries.len());
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('G') => {
+                    self.show_git_panel = false;
+                    self.error_message = None;
+                }
+                KeyCode::Char(' ') => self.toggle_staged_git_entry(),
+                KeyCode::Char('a') => self.toggle_staged_git_entries(),
+                KeyCode::Char('c') => self.start_commit(),
+                KeyCode::Char('P') => self.run_git(GitCommand::Push),
+                KeyCode::Char('p') => self.run_git(GitCommand::Pull),
+                KeyCode::Char('r') => self.refresh_git_status(),
+                KeyCode::Up | KeyCode::Char('k') => step_list(&mut self.git_list_state, len, false),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    step_list(&mut self.git_list_state, len, true)
+                }
+                _ => {}
+            }
+        } else if self.show_popup {
             match key.code {
                 KeyCode::Esc => {
                     self.show_popup = false;
                 }
                 KeyCode::Enter => {
-                    self.handle_popup_selection();
                     self.show_popup = false;
+                    self.handle_popup_selection();
                 }
                 KeyCode::Up | KeyCode::Char('k') => {
                     let i = match self.popup_state.selected() {
@@ -462,17 +3272,145 @@ impl App {
                 }
                 _ => {}
             }
+        } else if self.show_filter_popup {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('f') | KeyCode::Char('q') => {
+                    self.show_filter_popup = false;
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => self.toggle_highlighted_filter(),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    step_list(&mut self.filter_popup_state, Filters::items().len(), false)
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    step_list(&mut self.filter_popup_state, Filters::items().len(), true)
+                }
+                _ => {}
+            }
+        } else if self.show_add_popup {
+            // Options, then "Add" and "Cancel"
+            let len = AddOption::ALL.len() + 2;
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => {
+                    self.show_add_popup = false;
+                    self.add_targets.clear();
+                }
+                KeyCode::Char(' ') => self.handle_add_popup_selection(true),
+                KeyCode::Enter => self.handle_add_popup_selection(false),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    step_list(&mut self.add_popup_state, len, false)
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    step_list(&mut self.add_popup_state, len, true)
+                }
+                _ => {}
+            }
+        } else if let Some(dir) = &mut self.dir_input {
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit(),
+                KeyCode::Esc => self.dir_input = None,
+                KeyCode::Enter => self.submit_dir_input(),
+                KeyCode::Backspace => {
+                    dir.pop();
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => dir.push(c),
+                _ => {}
+            }
+        } else if self.diff_search_input {
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit(),
+                KeyCode::Esc => {
+                    self.diff_search_input = false;
+                    self.diff_search.clear();
+                    self.update_diff_matches();
+                }
+                KeyCode::Enter => {
+                    self.diff_search_input = false;
+                    self.update_diff_matches();
+                    self.jump_to_match(true);
+                }
+                KeyCode::Backspace => {
+                    self.diff_search.pop();
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.diff_search.push(c)
+                }
+                _ => {}
+            }
+        } else if self.focus == Focus::Diff {
+            let half_page = (self.diff_height / 2).max(1) as isize;
+            match (key.modifiers, key.code) {
+                (KeyModifiers::CONTROL, KeyCode::Char('c') | KeyCode::Char('C'))
+                | (_, KeyCode::Char('q')) => self.quit(),
+                (KeyModifiers::CONTROL, KeyCode::Char('d')) => self.scroll_diff_by(half_page),
+                (KeyModifiers::CONTROL, KeyCode::Char('u')) => self.scroll_diff_by(-half_page),
+                (_, KeyCode::Tab | KeyCode::Esc) => self.toggle_focus(),
+                (_, KeyCode::Down | KeyCode::Char('j')) => self.scroll_diff_by(1),
+                (_, KeyCode::Up | KeyCode::Char('k')) => self.scroll_diff_by(-1),
+                (_, KeyCode::PageDown) => self.scroll_diff_by(half_page * 2),
+                (_, KeyCode::PageUp) => self.scroll_diff_by(-half_page * 2),
+                (_, KeyCode::Char('g') | KeyCode::Home) => self.scroll_diff_to(0),
+                (_, KeyCode::Char('G') | KeyCode::End) => self.scroll_diff_to(usize::MAX),
+                (_, KeyCode::Char('/')) => self.start_diff_search(),
+                (_, KeyCode::Char('v')) => self.toggle_split_view(),
+                (_, KeyCode::Char('n')) => self.jump_to_match(true),
+                (_, KeyCode::Char('N')) => self.jump_to_match(false),
+                (_, KeyCode::Char(']')) => self.move_hunk_cursor(true),
+                (_, KeyCode::Char('[')) => self.move_hunk_cursor(false),
+                (_, KeyCode::Char(' ')) => self.toggle_selected_hunk(),
+                (_, KeyCode::Char('R')) => self.re_add_hunks(),
+                (_, KeyCode::Char('a') | KeyCode::Char('x')) => self.discard_hunks(),
+                _ => {}
+            }
+        } else if self.filter_input {
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit(),
+                KeyCode::Esc => self.clear_filter(),
+                KeyCode::Enter => self.filter_input = false,
+                KeyCode::Backspace => {
+                    let mut query = self.filter_query.clone();
+                    query.pop();
+                    self.update_filter(query);
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let query = format!("{}{}", self.filter_query, c);
+                    self.update_filter(query);
+                }
+                KeyCode::Up => self.previous_item(),
+                KeyCode::Down => self.next_item(),
+                _ => {}
+            }
         } else {
             match (key.modifiers, key.code) {
+                (_, KeyCode::Esc) if !self.filter_query.is_empty() => self.clear_filter(),
                 (_, KeyCode::Esc | KeyCode::Char('q'))
                 | (KeyModifiers::CONTROL, KeyCode::Char('c') | KeyCode::Char('C')) => self.quit(),
                 (_, KeyCode::Char(' ')) => self.toggle_selected_file(),
                 (_, KeyCode::Char('S')) => {
                     self.open_chezmoi_source();
                 }
-                (_, KeyCode::Char('A')) => self.show_action_popup(),
-                //(_, KeyCode::Char('A')) => self.apply_selected_files(),
+                (_, KeyCode::Char('A')) => self.plan_staged_files(),
+                (_, KeyCode::Char('<')) => self.stage_highlighted(Stage::ReAdd),
+                (_, KeyCode::Char('>')) => self.stage_highlighted(Stage::Apply),
                 (_, KeyCode::Char('e')) => self.edit_highlighted_file(),
+                (_, KeyCode::Char('m')) => self.merge_highlighted_file(),
+                (_, KeyCode::Char('B')) => self.toggle_bulk_diff(),
+                (_, KeyCode::Char('v')) => self.toggle_split_view(),
+                (_, KeyCode::Char('t')) => self.toggle_view_mode(),
+                (_, KeyCode::Enter) => self.toggle_collapsed_dir(),
+                (_, KeyCode::Char('/')) => self.start_filter(),
+                (_, KeyCode::Char('f')) => self.show_filter_popup(),
+                (_, KeyCode::Char('1')) => self.switch_view(View::Status),
+                (_, KeyCode::Char('2')) => self.switch_view(View::Managed),
+                (_, KeyCode::Char('3')) => self.switch_view(View::Unmanaged),
+                (_, KeyCode::Char('F')) => self.show_forget_popup(),
+                (_, KeyCode::Char('c')) => self.show_chattr_popup(),
+                (_, KeyCode::Char('a')) => self.show_add_popup(),
+                (_, KeyCode::Char('D')) => self.show_discard_popup(),
+                (_, KeyCode::Char('G')) => self.open_git_panel(),
+                (_, KeyCode::Char('L')) => self.open_git_ui(),
+                (_, KeyCode::Char('U')) => self.show_update_popup(),
+                (_, KeyCode::Tab) => self.toggle_focus(),
+                (_, KeyCode::Char('d')) => self.start_dir_input(),
                 (_, KeyCode::Up | KeyCode::Char('k')) => self.previous_item(),
                 (_, KeyCode::Down | KeyCode::Char('j')) => self.next_item(),
                 _ => {}
@@ -481,9 +3419,12 @@ impl App {
     }
 
     fn next_item(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
         let i = match self.list_state.selected() {
             Some(i) => {
-                if i >= self.files.len() - 1 {
+                if i >= self.rows.len() - 1 {
                     0
                 } else {
                     i + 1
@@ -496,10 +3437,13 @@ impl App {
     }
 
     fn previous_item(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
         let i = match self.list_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.files.len() - 1
+                    self.rows.len() - 1
                 } else {
                     i - 1
                 }
@@ -510,3 +3454,373 @@ impl App {
         self.update_selected_diff();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::chezmoi::ScriptedBackend;
+
+    fn app(backend: ScriptedBackend) -> (App, Arc<ScriptedBackend>) {
+        let backend = Arc::new(backend);
+        let mut app = App::with_backend(Arc::clone(&backend) as Arc<dyn ChezmoiBackend>);
+        settle(&mut app);
+        (app, backend)
+    }
+
+    /// Handles worker outputs until no job is left in flight.
+    fn settle(app: &mut App) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            app.handle_worker_output();
+            if !app.worker.is_busy() {
+                return;
+            }
+            assert!(Instant::now() < deadline, "the worker did not finish");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.on_key_event(KeyEvent::new(code, KeyModifiers::NONE));
+        settle(app);
+    }
+
+    fn highlight(app: &mut App, path: &str) {
+        app.rebuild_rows_at(Some(path.to_string()));
+        assert_eq!(app.get_highlighted_path().as_deref(), Some(path));
+    }
+
+    fn selection(app: &App, path: &str) -> Selection {
+        app.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.selected.clone())
+            .unwrap()
+    }
+
+    fn screen(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn status_is_loaded_into_the_list() {
+        let (mut app, backend) =
+            app(ScriptedBackend::new().with_status(&["MM .bashrc", " M .config/git/config"]));
+        let statuses: Vec<(&str, &FileStatus, &FileStatus)> = app
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), &f.local_status, &f.source_status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (".bashrc", &FileStatus::Modified, &FileStatus::Modified),
+                (
+                    ".config/git/config",
+                    &FileStatus::Unchanged,
+                    &FileStatus::Modified
+                ),
+            ]
+        );
+        assert_eq!(backend.calls()[0], "status");
+        assert!(app.error_message.is_none());
+        let screen = screen(&mut app);
+        assert!(screen.contains(".bashrc"));
+        assert!(screen.contains(".config/git/config"));
+    }
+
+    #[test]
+    fn the_highlighted_diff_comes_from_the_backend() {
+        let diff = "diff --git a/.bashrc b/.bashrc\n@@ -1 +1 @@\n-old\n+new\n";
+        let (mut app, backend) = app(ScriptedBackend::new()
+            .with_status(&["MM .bashrc"])
+            .with_diff(".bashrc", diff));
+        assert_eq!(app.chezmoi_file_diff, diff);
+        assert!(backend.calls().contains(&"diff .bashrc".to_string()));
+        assert!(screen(&mut app).contains("+new"));
+    }
+
+    #[test]
+    fn switching_diff_modes_fetches_the_diffs_again() {
+        let diff = "diff --git a/.bashrc b/.bashrc\n@@ -1 +1 @@\n-old\n+new\n";
+        let (mut app, backend) = app(ScriptedBackend::new()
+            .with_status(&["MM .bashrc"])
+            .with_diff(".bashrc", diff)
+            .with_external_diff(".bashrc", "\u{1b}[32mrendered\u{1b}[0m\n"));
+        app.config.diff.external = true;
+
+        press(&mut app, KeyCode::Char('B'));
+        settle(&mut app);
+        assert!(backend.calls().contains(&"diff".to_string()));
+        assert_eq!(app.chezmoi_file_diff, diff);
+        assert!(app.rendered_diff.is_none());
+
+        press(&mut app, KeyCode::Char('B'));
+        settle(&mut app);
+        assert!(app.rendered_diff.is_some());
+        assert!(screen(&mut app).contains("rendered"));
+    }
+
+    #[test]
+    fn help_bar_shows_every_key_of_the_focused_pane() {
+        let (mut app, _) = app(ScriptedBackend::new()
+            .with_status(&["MM .bashrc"])
+            .with_diff(".bashrc", "@@ -1 +1 @@\n-old\n+new\n"));
+        for code in [
+            KeyCode::Char('2'),
+            KeyCode::Char('3'),
+            KeyCode::Char('1'),
+            KeyCode::Tab,
+        ] {
+            press(&mut app, code);
+            let screen = screen(&mut app);
+            let bar: Vec<&str> = screen.lines().rev().take(3).collect();
+            let bar = bar
+                .into_iter()
+                .rev()
+                .flat_map(str::split_whitespace)
+                .collect::<Vec<_>>()
+                .join(" ");
+            for (key, label) in app.help_keys() {
+                assert!(
+                    bar.contains(&format!("{} {}", key, label)),
+                    "{} {} missing from {:?}",
+                    key,
+                    label,
+                    bar
+                );
+            }
+        }
+        assert_eq!(app.focus, Focus::Diff);
+    }
+
+    #[test]
+    fn status_errors_are_shown() {
+        let (mut app, _) =
+            app(ScriptedBackend::new().with_status_error("chezmoi: invalid config file"));
+        assert!(app.files.is_empty());
+        assert!(app
+            .error_message
+            .as_deref()
+            .is_some_and(|e| e.contains("invalid config file")));
+        assert!(screen(&mut app).contains("invalid config file"));
+    }
+
+    #[test]
+    fn removed_dashes_are_not_file_headers() {
+        let diff = "diff --git a/init.lua b/init.lua
+--- a/init.lua
++++ b/init.lua
+@@ -1,2 +1,2 @@
+---- settings
++-- settings
+ vim.o.number = true
+";
+        let prefixes: Vec<Option<char>> = hunk_line_prefixes(diff)
+            .into_iter()
+            .map(|(_, prefix)| prefix)
+            .collect();
+        assert_eq!(
+            prefixes,
+            [None, None, None, None, Some('-'), Some('+'), Some(' ')]
+        );
+    }
+
+    #[test]
+    fn source_templates_are_highlighted_when_discarding() {
+        let (mut app, _) = app(ScriptedBackend::new());
+        app.discard_plan = Some(DiscardPlan {
+            path: ".gitconfig".to_string(),
+            side: DiscardSide::Source,
+            method: DiscardMethod::GitCheckout {
+                source_path: "/source/dot_gitconfig.tmpl".to_string(),
+            },
+            diff: "diff --git a/dot_gitconfig.tmpl b/dot_gitconfig.tmpl
+@@ -1 +1 @@
+-email = old@example.com
++email = {{ .email }}
+"
+            .to_string(),
+        });
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let action = terminal
+            .backend()
+            .buffer()
+            .content()
+            .windows(2)
+            .find(|cells| cells[0].symbol() == "{" && cells[1].symbol() == "{")
+            .map(|cells| cells[0].clone())
+            .unwrap();
+        assert_eq!(action.fg, Color::LightMagenta);
+    }
+
+    #[test]
+    fn managed_only_actions_are_refused_elsewhere() {
+        let (mut app, backend) = app(ScriptedBackend::new().with_status(&["MM .bashrc"]));
+        press(&mut app, KeyCode::Char('F'));
+        assert!(!app.show_popup);
+        assert_eq!(
+            app.error_message.as_deref(),
+            Some("Forgetting files is not available here, switch to the Managed view")
+        );
+        assert!(!backend.calls().iter().any(|c| c.starts_with("forget")));
+    }
+
+    #[test]
+    fn files_are_staged_on_the_side_they_changed() {
+        let (mut app, _) = app(ScriptedBackend::new().with_status(&["MM .bashrc", " M .zshrc"]));
+        highlight(&mut app, ".bashrc");
+        press(&mut app, KeyCode::Char('<'));
+        assert_eq!(selection(&app, ".bashrc"), Selection::Local);
+        press(&mut app, KeyCode::Char('>'));
+        assert_eq!(selection(&app, ".bashrc"), Selection::Source);
+
+        // Only changed in the source state, so there is nothing to re-add
+        highlight(&mut app, ".zshrc");
+        press(&mut app, KeyCode::Char('<'));
+        assert_eq!(selection(&app, ".zshrc"), Selection::None);
+        assert_eq!(
+            app.error_message.as_deref(),
+            Some("Nothing to re-add: .zshrc")
+        );
+
+        assert_eq!(app.get_selected_local_files(), Vec::<String>::new());
+        assert_eq!(app.get_selected_source_files(), [".bashrc"]);
+    }
+
+    #[test]
+    fn space_cycles_through_the_staging_panes() {
+        let (mut app, _) = app(ScriptedBackend::new().with_status(&["MM .bashrc"]));
+        highlight(&mut app, ".bashrc");
+        let mut selections = Vec::new();
+        for _ in 0..3 {
+            press(&mut app, KeyCode::Char(' '));
+            selections.push(selection(&app, ".bashrc"));
+        }
+        assert_eq!(
+            selections,
+            [Selection::Local, Selection::Source, Selection::None]
+        );
+    }
+
+    #[test]
+    fn space_on_a_directory_selects_every_file_below_it() {
+        let (mut app, _) = app(ScriptedBackend::new().with_status(&[
+            "MM .config/git/config",
+            " M .config/nvim/init.lua",
+            " M .bashrc",
+        ]));
+        press(&mut app, KeyCode::Char('t'));
+        highlight(&mut app, ".config/nvim/init.lua");
+        press(&mut app, KeyCode::Char('>'));
+        highlight(&mut app, ".config");
+        press(&mut app, KeyCode::Char(' '));
+        // Files already staged keep their pane
+        assert_eq!(selection(&app, ".config/git/config"), Selection::Local);
+        assert_eq!(selection(&app, ".config/nvim/init.lua"), Selection::Source);
+        assert_eq!(selection(&app, ".bashrc"), Selection::None);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(selection(&app, ".config/git/config"), Selection::None);
+        assert_eq!(selection(&app, ".config/nvim/init.lua"), Selection::None);
+    }
+
+    fn stage_both_panes(app: &mut App) {
+        highlight(app, ".bashrc");
+        press(app, KeyCode::Char('<'));
+        highlight(app, ".zshrc");
+        press(app, KeyCode::Char('>'));
+        press(app, KeyCode::Char('A'));
+        assert!(app.execution_plan.is_some());
+        press(app, KeyCode::Char('y'));
+    }
+
+    #[test]
+    fn execute_re_adds_then_applies() {
+        let (mut app, backend) =
+            app(ScriptedBackend::new().with_status(&["M  .bashrc", " M .zshrc"]));
+        stage_both_panes(&mut app);
+        let calls = backend.calls();
+        let position = |call: &str| calls.iter().position(|c| c == call).unwrap();
+        assert!(position("re-add .bashrc") < position("apply --force .zshrc"));
+        assert!(app.files.is_empty());
+        assert!(app.error_message.is_none());
+        assert!(app.show_batch_results);
+    }
+
+    #[test]
+    fn confirmed_conflicts_are_overwritten() {
+        let (mut app, backend) = app(ScriptedBackend::new().with_status(&["MM .bashrc"]));
+        highlight(&mut app, ".bashrc");
+        press(&mut app, KeyCode::Char('>'));
+        press(&mut app, KeyCode::Char('A'));
+        assert!(screen(&mut app).contains("local changes are lost"));
+        press(&mut app, KeyCode::Char('y'));
+        assert!(backend
+            .calls()
+            .contains(&"apply --force .bashrc".to_string()));
+        assert!(app.files.is_empty());
+    }
+
+    #[test]
+    fn failed_re_add_skips_the_applies_and_keeps_everything_staged() {
+        let (mut app, backend) = app(ScriptedBackend::new()
+            .with_status(&["M  .bashrc", " M .zshrc"])
+            .with_re_add_error("chezmoi: .bashrc: permission denied"));
+        stage_both_panes(&mut app);
+        assert!(backend.calls().contains(&"re-add .bashrc".to_string()));
+        assert!(!backend
+            .calls()
+            .iter()
+            .any(|c| c.starts_with("apply --force")));
+        assert_eq!(selection(&app, ".bashrc"), Selection::Local);
+        assert_eq!(selection(&app, ".zshrc"), Selection::Source);
+        assert_eq!(
+            app.error_message.as_deref(),
+            Some("1 file(s) failed, they are still staged")
+        );
+        let results = app.batch_results.as_ref().unwrap();
+        assert!(matches!(
+            results.get(Stage::Apply).as_slice(),
+            [(path, None)] if path == ".zshrc"
+        ));
+    }
+
+    #[test]
+    fn only_failed_files_stay_staged() {
+        let (mut app, backend) = app(ScriptedBackend::new()
+            .with_status(&[" M .bashrc", " M .zshrc"])
+            .with_path_error(".zshrc", "chezmoi: .zshrc: template: undefined variable"));
+        highlight(&mut app, ".bashrc");
+        press(&mut app, KeyCode::Char('>'));
+        highlight(&mut app, ".zshrc");
+        press(&mut app, KeyCode::Char('>'));
+        press(&mut app, KeyCode::Char('A'));
+        press(&mut app, KeyCode::Char('y'));
+        assert!(backend
+            .calls()
+            .contains(&"apply --force .bashrc".to_string()));
+        assert_eq!(
+            app.files
+                .iter()
+                .map(|f| f.path.as_str())
+                .collect::<Vec<_>>(),
+            [".zshrc"]
+        );
+        assert_eq!(selection(&app, ".zshrc"), Selection::Source);
+        assert!(screen(&mut app).contains("undefined variable"));
+    }
+}
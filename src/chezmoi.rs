@@ -1,10 +1,288 @@
-use std::process::Command;
+use std::collections::HashMap;
+use std::fmt;
+use std::process::{Command, Output};
+use std::sync::Mutex;
 
 use crate::app::{FileItem, Selection};
 use crate::utils;
 
 pub const HOME: &str = "~/";
 
+/// How much of an unmanaged file is read for its preview.
+const PREVIEW_BYTES: usize = 64 * 1024;
+
+/// Why a chezmoi invocation failed.
+#[derive(Debug)]
+pub enum ChezmoiError {
+    /// The process could not be started at all.
+    Spawn {
+        command: String,
+        source: std::io::Error,
+    },
+    /// The process ran but exited with a non-zero code, e.g. chezmoi or the git UI.
+    Exit {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// A source template failed to execute.
+    Template(String),
+    /// An encrypted file or a password manager lookup failed.
+    Decryption(String),
+    /// chezmoi's output could not be understood.
+    Parse(String),
+    /// A file could not be read or written directly.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// The operation was refused before running chezmoi, e.g. because it would lose data.
+    Unsupported(String),
+}
+
+impl ChezmoiError {
+    /// Classifies a failed run of `command` from its exit code and stderr.
+    pub fn from_exit(command: &str, code: Option<i32>, stderr: &str) -> Self {
+        let stderr = stderr.trim().to_string();
+        let lower = stderr.to_lowercase();
+        if lower.contains("template:") {
+            ChezmoiError::Template(stderr)
+        } else if lower.contains("decrypt")
+            || lower.contains("gpg")
+            || lower.contains("age:")
+            || ["lpass", "bw", "op", "pass", "keepassxc-cli", "gopass"]
+                .iter()
+                .any(|manager| lower.contains(&format!("exec: \"{}\"", manager)))
+        {
+            ChezmoiError::Decryption(stderr)
+        } else {
+            ChezmoiError::Exit {
+                command: command.to_string(),
+                code,
+                stderr,
+            }
+        }
+    }
+}
+
+impl fmt::Display for ChezmoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChezmoiError::Spawn { command, source }
+                if source.kind() == std::io::ErrorKind::NotFound =>
+            {
+                write!(f, "failed to execute {}: not found in PATH", command)
+            }
+            ChezmoiError::Spawn { command, source } => {
+                write!(f, "failed to execute {}: {}", command, source)
+            }
+            ChezmoiError::Exit {
+                command,
+                code,
+                stderr,
+            } => {
+                match code {
+                    Some(code) => write!(f, "{} exited with code {}", command, code)?,
+                    None => write!(f, "{} was terminated", command)?,
+                }
+                // Interactive commands write their errors to the terminal instead
+                if stderr.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, ": {}", stderr)
+                }
+            }
+            ChezmoiError::Template(stderr) => write!(f, "template error: {}", stderr),
+            ChezmoiError::Decryption(stderr) => write!(f, "decryption error: {}", stderr),
+            ChezmoiError::Parse(message) => {
+                write!(f, "could not parse chezmoi output: {}", message)
+            }
+            ChezmoiError::Io { path, source } => write!(f, "{}: {}", path, source),
+            ChezmoiError::Unsupported(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ChezmoiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChezmoiError::Spawn { source, .. } | ChezmoiError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type ChezmoiResult<T> = Result<T, ChezmoiError>;
+
+/// Target entry types understood by chezmoi's `--include`/`--exclude` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryType {
+    Files,
+    Dirs,
+    Symlinks,
+    Scripts,
+    Encrypted,
+    Templates,
+}
+
+impl EntryType {
+    pub const ALL: [EntryType; 6] = [
+        EntryType::Files,
+        EntryType::Dirs,
+        EntryType::Symlinks,
+        EntryType::Scripts,
+        EntryType::Encrypted,
+        EntryType::Templates,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::Files => "files",
+            EntryType::Dirs => "dirs",
+            EntryType::Symlinks => "symlinks",
+            EntryType::Scripts => "scripts",
+            EntryType::Encrypted => "encrypted",
+            EntryType::Templates => "templates",
+        }
+    }
+}
+
+/// Flags of `chezmoi add` that can be toggled from the add popup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddOption {
+    Encrypt,
+    Template,
+    Autotemplate,
+    Exact,
+    Follow,
+    Create,
+}
+
+impl AddOption {
+    pub const ALL: [AddOption; 6] = [
+        AddOption::Encrypt,
+        AddOption::Template,
+        AddOption::Autotemplate,
+        AddOption::Exact,
+        AddOption::Follow,
+        AddOption::Create,
+    ];
+
+    pub fn flag(&self) -> &'static str {
+        match self {
+            AddOption::Encrypt => "--encrypt",
+            AddOption::Template => "--template",
+            AddOption::Autotemplate => "--autotemplate",
+            AddOption::Exact => "--exact",
+            AddOption::Follow => "--follow",
+            AddOption::Create => "--create",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            AddOption::Encrypt => "Encrypt files",
+            AddOption::Template => "Add as templates",
+            AddOption::Autotemplate => "Generate templates from config values",
+            AddOption::Exact => "Make directories exact",
+            AddOption::Follow => "Follow symlinks",
+            AddOption::Create => "Only create the file if missing",
+        }
+    }
+}
+
+/// Entry types passed to chezmoi as `--include`/`--exclude`. Empty means everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntryTypeFilter {
+    pub include: Vec<EntryType>,
+    pub exclude: Vec<EntryType>,
+}
+
+impl EntryTypeFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether an entry of the given types is kept, following chezmoi's rules: it must match
+    /// an included type (if any) and no excluded one.
+    pub fn matches(&self, types: &[EntryType]) -> bool {
+        (self.include.is_empty() || types.iter().any(|t| self.include.contains(t)))
+            && !types.iter().any(|t| self.exclude.contains(t))
+    }
+
+    fn args(&self) -> Vec<String> {
+        let join = |types: &[EntryType]| {
+            types
+                .iter()
+                .map(EntryType::as_str)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let mut args = Vec::new();
+        if !self.include.is_empty() {
+            args.push(format!("--include={}", join(&self.include)));
+        }
+        if !self.exclude.is_empty() {
+            args.push(format!("--exclude={}", join(&self.exclude)));
+        }
+        args
+    }
+}
+
+/// Everything the TUI needs from chezmoi.
+///
+/// `App` only talks to chezmoi through this trait so it can be driven by the real binary
+/// ([`ProcessBackend`]) or by canned data ([`ScriptedBackend`]).
+pub trait ChezmoiBackend: std::fmt::Debug + Send + Sync {
+    fn status(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<FileItem>>;
+    /// `chezmoi diff` of one file as a unified diff: chezmoi's built-in diff, whatever pager
+    /// or diff command is configured.
+    fn diff(&self, path: &str) -> ChezmoiResult<String>;
+    /// `chezmoi diff` of one file with its colours, through the diff pager or command
+    /// configured in chezmoi.
+    fn diff_external(&self, path: &str) -> ChezmoiResult<String>;
+    /// Unified diff of every managed file at once, as printed by a bare `chezmoi diff`.
+    fn diff_all(&self) -> ChezmoiResult<String>;
+    fn re_add(&self, selected_files: &[String]) -> ChezmoiResult<()>;
+    fn apply(&self, selected_files: &[String]) -> ChezmoiResult<()>;
+    /// `chezmoi apply --force`: overwrites targets even when they changed since chezmoi last
+    /// wrote them.
+    fn force_apply(&self, selected_files: &[String]) -> ChezmoiResult<()>;
+    /// What `apply` would do to the given files, as printed by
+    /// `chezmoi apply --dry-run --verbose`: one git-style diff section per changed target.
+    fn dry_run_apply(&self, selected_files: &[String]) -> ChezmoiResult<String>;
+    fn edit(&self, highlighted_file: String) -> ChezmoiResult<()>;
+    fn open_source(&self) -> ChezmoiResult<()>;
+    /// Runs a git UI such as lazygit in the source directory. `command` is the program and
+    /// its arguments, where `{source}` stands for the source directory.
+    fn open_git_ui(&self, command: &[String]) -> ChezmoiResult<()>;
+    /// Runs `chezmoi merge`, a three-way merge of the target, the target state and the
+    /// source state in the configured merge tool.
+    fn merge(&self, path: &str) -> ChezmoiResult<()>;
+    /// Every target path managed by chezmoi, relative to the destination directory.
+    fn managed(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<String>>;
+    /// Target state contents of a file, as `chezmoi cat` prints it.
+    fn cat(&self, path: &str) -> ChezmoiResult<String>;
+    fn forget(&self, selected_files: &[String]) -> ChezmoiResult<()>;
+    /// Runs `chezmoi chattr`, e.g. with `+template` or `-private,executable`.
+    fn chattr(&self, attributes: &str, selected_files: &[String]) -> ChezmoiResult<()>;
+    /// Paths under `dir` (relative to the destination directory, empty for all of it) that
+    /// chezmoi doesn't manage.
+    fn unmanaged(&self, dir: &str) -> ChezmoiResult<Vec<String>>;
+    /// Current contents of a target, or its entries when it is a directory.
+    fn read_target(&self, path: &str) -> ChezmoiResult<String>;
+    fn add(&self, options: &[AddOption], selected_files: &[String]) -> ChezmoiResult<()>;
+    /// Absolute path of a target, relative to the destination directory.
+    fn target_path(&self, path: &str) -> String;
+    /// Absolute path of the source state file of a target.
+    fn source_path(&self, path: &str) -> ChezmoiResult<String>;
+    /// Runs git in the source directory (`chezmoi git -- <args>`) and returns its output.
+    fn git(&self, args: &[&str]) -> ChezmoiResult<String>;
+    /// Reads a file by absolute path, e.g. a source file to patch.
+    fn read_file(&self, path: &str) -> ChezmoiResult<String>;
+    fn write_file(&self, path: &str, contents: &str) -> ChezmoiResult<()>;
+}
+
 pub fn check_installed() -> color_eyre::Result<()> {
     match std::process::Command::new("chezmoi")
         .arg("--version")
@@ -21,91 +299,860 @@ pub fn check_installed() -> color_eyre::Result<()> {
     }
 }
 
-// TODO: Should this return a Result?
-pub fn update_status() -> Vec<FileItem> {
-    let output = Command::new("chezmoi")
-        .arg("status")
-        .stdin(std::process::Stdio::inherit()) // Allows user to enter lpass password if needed
-        .output()
-        .unwrap_or_else(|_| panic!("failed to execute chezmoi status"));
-
-    let files: Vec<FileItem> = String::from_utf8_lossy(&output.stdout)
+fn parse_status(output: &str) -> ChezmoiResult<Vec<FileItem>> {
+    output
         .lines()
+        .filter(|line| !line.trim().is_empty())
         .map(|line| {
-            let (path, local_status, source_status) = utils::extract_filename_and_status(line);
-            FileItem {
+            let (path, local_status, source_status) =
+                utils::extract_filename_and_status(line).map_err(ChezmoiError::Parse)?;
+            Ok(FileItem {
                 path,
                 selected: Selection::None,
                 local_status,
                 source_status,
-            }
+            })
         })
-        .collect();
-    files
+        .collect()
 }
 
-// TODO: Should this return a Result?
-pub fn diff(path: &str) -> String {
-    let output = Command::new("chezmoi")
-        .arg("diff")
-        .arg(format!("{}{}", HOME, path))
-        .output()
-        .unwrap_or_else(|_| panic!("failed to execute chezmoi diff"));
+/// Splits the combined output of `chezmoi diff` into one diff per target path.
+///
+/// Sections start at each `diff --git a/<path> b/<path>` header; the header is kept so every
+/// piece looks like what `chezmoi diff <path>` would print.
+pub fn split_diff(output: &str) -> HashMap<String, String> {
+    let mut diffs = HashMap::new();
+    let mut current: Option<(String, String)> = None;
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("diff --git a/") {
+            if let Some((path, diff)) = current.take() {
+                diffs.insert(path, diff);
+            }
+            let path = header
+                .split_once(" b/")
+                .map_or(header, |(path, _)| path)
+                .to_string();
+            current = Some((path, String::new()));
+        }
+        if let Some((_, diff)) = current.as_mut() {
+            diff.push_str(line);
+            diff.push('\n');
+        }
+    }
+    if let Some((path, diff)) = current {
+        diffs.insert(path, diff);
+    }
+    diffs
+}
 
-    // Strip ANSI escape sequences from the output
-    let diff = String::from_utf8_lossy(&output.stdout).to_string();
-    let stripped = strip_ansi_escapes::strip(&diff);
+fn strip_ansi(output: &[u8]) -> String {
+    let stripped = strip_ansi_escapes::strip(output);
     String::from_utf8_lossy(&stripped).to_string()
 }
 
-pub fn re_add(selected_files: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+/// A chezmoi command run in the background while the TUI reads the keyboard. It gets
+/// neither stdin nor a TTY, so a prompt (a password manager, a gpg passphrase, an overwrite
+/// question) fails with its error instead of taking keystrokes or waiting forever.
+fn chezmoi() -> Command {
     let mut command = Command::new("chezmoi");
-    command.arg("re-add");
+    command.arg("--no-tty").stdin(std::process::Stdio::null());
+    command
+}
+
+/// Runs a command to completion, turning spawn failures and non-zero exits into errors.
+fn run(command: &mut Command) -> ChezmoiResult<Output> {
+    let output = command
+        .output()
+        .map_err(|source| spawn_error(command, source))?;
+    if !output.status.success() {
+        return Err(ChezmoiError::from_exit(
+            &command.get_program().to_string_lossy(),
+            output.status.code(),
+            &String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    Ok(output)
+}
 
-    // Add each file as a separate argument
-    for file in selected_files {
-        command.arg(format!("{}{}", HOME, file));
+/// Runs an interactive command (e.g. an editor) attached to the terminal.
+fn run_interactive(command: &mut Command) -> ChezmoiResult<()> {
+    let status = command
+        .spawn()
+        .and_then(|mut child| child.wait())
+        .map_err(|source| spawn_error(command, source))?;
+    if !status.success() {
+        return Err(ChezmoiError::Exit {
+            command: command.get_program().to_string_lossy().to_string(),
+            code: status.code(),
+            stderr: String::new(),
+        });
     }
+    Ok(())
+}
 
-    let output = command.output()?;
+fn spawn_error(command: &Command, source: std::io::Error) -> ChezmoiError {
+    ChezmoiError::Spawn {
+        command: command.get_program().to_string_lossy().to_string(),
+        source,
+    }
+}
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).into());
+// --------------------------------------------------------
+// Process backend
+// --------------------------------------------------------
+
+/// Runs the `chezmoi` binary found in `$PATH`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessBackend;
+
+impl ChezmoiBackend for ProcessBackend {
+    fn status(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<FileItem>> {
+        let output = run(chezmoi().arg("status").args(types.args()))?;
+
+        parse_status(&String::from_utf8_lossy(&output.stdout))
     }
 
-    Ok(())
+    fn diff(&self, path: &str) -> ChezmoiResult<String> {
+        // The configured pager and diff command also apply to captured output, and hunks,
+        // the split view and search need a unified diff
+        let output = run(chezmoi()
+            .arg("--no-pager")
+            .arg("diff")
+            .arg("--use-builtin-diff")
+            .arg(format!("{}{}", HOME, path)))?;
+
+        // Strip ANSI escape sequences from the output
+        Ok(strip_ansi(&output.stdout))
+    }
+
+    fn diff_external(&self, path: &str) -> ChezmoiResult<String> {
+        let output = run(chezmoi()
+            .arg("diff")
+            .arg("--color=true")
+            .arg(format!("{}{}", HOME, path))
+            // difftastic only colours its output on a terminal by default
+            .env("DFT_COLOR", "always"))?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn diff_all(&self) -> ChezmoiResult<String> {
+        let output = run(chezmoi()
+            .arg("--no-pager")
+            .arg("diff")
+            .arg("--use-builtin-diff"))?;
+        Ok(strip_ansi(&output.stdout))
+    }
+
+    fn re_add(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        command.arg("re-add");
+
+        // Add each file as a separate argument
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn apply(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        command.arg("apply");
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn force_apply(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        command.arg("apply").arg("--force");
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn dry_run_apply(&self, selected_files: &[String]) -> ChezmoiResult<String> {
+        let mut command = chezmoi();
+        // --force only skips the overwrite prompts; nothing is written in a dry run
+        // The verbose output of apply is always chezmoi's own diff, only the pager applies
+        command
+            .arg("--no-pager")
+            .arg("apply")
+            .arg("--dry-run")
+            .arg("--verbose")
+            .arg("--force");
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        let output = run(&mut command)?;
+        Ok(strip_ansi(&output.stdout))
+    }
+
+    fn edit(&self, highlighted_file: String) -> ChezmoiResult<()> {
+        run_interactive(
+            Command::new("chezmoi")
+                .arg("edit")
+                .arg(format!("{}{}", HOME, highlighted_file)),
+        )
+    }
+
+    fn open_source(&self) -> ChezmoiResult<()> {
+        run_interactive(Command::new("chezmoi").arg("edit"))
+    }
+
+    fn open_git_ui(&self, command: &[String]) -> ChezmoiResult<()> {
+        let Some((program, args)) = command.split_first() else {
+            return Err(ChezmoiError::Unsupported(
+                "no git UI command configured".to_string(),
+            ));
+        };
+        let output = run(chezmoi().arg("source-path"))?;
+        let source_dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        run_interactive(
+            Command::new(program)
+                .args(args.iter().map(|arg| arg.replace("{source}", &source_dir)))
+                .current_dir(&source_dir),
+        )
+    }
+
+    fn merge(&self, path: &str) -> ChezmoiResult<()> {
+        run_interactive(
+            Command::new("chezmoi")
+                .arg("merge")
+                .arg(format!("{}{}", HOME, path)),
+        )
+    }
+
+    fn managed(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<String>> {
+        let output = run(chezmoi()
+            .arg("managed")
+            .arg("--path-style=relative")
+            .args(types.args()))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn cat(&self, path: &str) -> ChezmoiResult<String> {
+        let output = run(chezmoi().arg("cat").arg(format!("{}{}", HOME, path)))?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn forget(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        command.arg("forget").arg("--force");
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn chattr(&self, attributes: &str, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        // `--` so that removals like `-template` aren't taken for flags
+        command.arg("chattr").arg("--").arg(attributes);
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn unmanaged(&self, dir: &str) -> ChezmoiResult<Vec<String>> {
+        let output = run(chezmoi()
+            .arg("unmanaged")
+            .arg("--path-style=relative")
+            .arg(format!("{}{}", HOME, dir)))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn read_target(&self, path: &str) -> ChezmoiResult<String> {
+        let target = std::path::PathBuf::from(self.target_path(path));
+        let read_error = |source| ChezmoiError::Io {
+            path: path.to_string(),
+            source,
+        };
+
+        if target.is_dir() {
+            let mut entries: Vec<String> = std::fs::read_dir(&target)
+                .map_err(read_error)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if entry.path().is_dir() {
+                        format!("{}/", name)
+                    } else {
+                        name
+                    }
+                })
+                .collect();
+            entries.sort();
+            return Ok(entries.join("\n"));
+        }
+
+        // Only the beginning matters for a preview
+        let mut contents = std::fs::read(&target).map_err(read_error)?;
+        contents.truncate(PREVIEW_BYTES);
+        Ok(String::from_utf8_lossy(&contents).to_string())
+    }
+
+    fn add(&self, options: &[AddOption], selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut command = chezmoi();
+        command.arg("add");
+        command.args(options.iter().map(AddOption::flag));
+
+        for file in selected_files {
+            command.arg(format!("{}{}", HOME, file));
+        }
+
+        run(&mut command).map(|_| ())
+    }
+
+    fn target_path(&self, path: &str) -> String {
+        let home = std::env::var_os("HOME").unwrap_or_default();
+        std::path::Path::new(&home)
+            .join(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn source_path(&self, path: &str) -> ChezmoiResult<String> {
+        let output = run(chezmoi()
+            .arg("source-path")
+            .arg(format!("{}{}", HOME, path)))?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn git(&self, args: &[&str]) -> ChezmoiResult<String> {
+        // Credentials can't be prompted for under the TUI
+        let output = run(chezmoi()
+            .arg("git")
+            .arg("--")
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0"))?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn read_file(&self, path: &str) -> ChezmoiResult<String> {
+        std::fs::read_to_string(path).map_err(|source| ChezmoiError::Io {
+            path: path.to_string(),
+            source,
+        })
+    }
+
+    fn write_file(&self, path: &str, contents: &str) -> ChezmoiResult<()> {
+        std::fs::write(path, contents).map_err(|source| ChezmoiError::Io {
+            path: path.to_string(),
+            source,
+        })
+    }
 }
 
-pub fn apply(selected_files: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    let mut command = Command::new("chezmoi");
-    command.arg("apply");
+// --------------------------------------------------------
+// Scripted backend
+// --------------------------------------------------------
+
+/// In-memory backend returning canned results, for driving `App` without chezmoi.
+///
+/// Status is kept as raw `chezmoi status` lines. A successful apply or re-add drops the
+/// affected paths from it, the same way a real run brings them back in sync. Every call is
+/// recorded and can be inspected with [`ScriptedBackend::calls`].
+#[derive(Debug, Default)]
+pub struct ScriptedBackend {
+    status: Mutex<Vec<String>>,
+    status_error: Option<String>,
+    entry_types: HashMap<String, Vec<EntryType>>,
+    managed: Mutex<Vec<String>>,
+    unmanaged: Mutex<Vec<String>>,
+    contents: HashMap<String, String>,
+    diffs: HashMap<String, String>,
+    external_diffs: HashMap<String, String>,
+    source_paths: HashMap<String, String>,
+    git_outputs: HashMap<String, String>,
+    git_dir: Option<std::path::PathBuf>,
+    files: Mutex<HashMap<String, String>>,
+    apply_error: Option<String>,
+    re_add_error: Option<String>,
+    path_errors: HashMap<String, String>,
+    calls: Mutex<Vec<String>>,
+}
 
-    for file in selected_files {
-        command.arg(format!("{}{}", HOME, file));
+impl ScriptedBackend {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    let output = command.output()?;
+    /// Sets the `chezmoi status` output, one line per entry (e.g. `" M .bashrc"`).
+    pub fn with_status(mut self, lines: &[&str]) -> Self {
+        self.status = Mutex::new(lines.iter().map(|l| l.to_string()).collect());
+        self
+    }
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).into());
+    /// Makes every `status` fail with the given stderr.
+    pub fn with_status_error(mut self, stderr: &str) -> Self {
+        self.status_error = Some(stderr.to_string());
+        self
     }
 
-    Ok(())
+    /// Sets the entry types of a path, used to honour `--include`/`--exclude` in `status`.
+    /// Paths default to plain files.
+    pub fn with_entry_types(mut self, path: &str, types: &[EntryType]) -> Self {
+        self.entry_types.insert(path.to_string(), types.to_vec());
+        self
+    }
+
+    /// Sets the `chezmoi managed` output.
+    pub fn with_managed(mut self, paths: &[&str]) -> Self {
+        self.managed = Mutex::new(paths.iter().map(|p| p.to_string()).collect());
+        self
+    }
+
+    /// Sets the `chezmoi unmanaged` output.
+    pub fn with_unmanaged(mut self, paths: &[&str]) -> Self {
+        self.unmanaged = Mutex::new(paths.iter().map(|p| p.to_string()).collect());
+        self
+    }
+
+    /// Sets what `chezmoi cat` and `read_target` return for a path.
+    pub fn with_contents(mut self, path: &str, contents: &str) -> Self {
+        self.contents.insert(path.to_string(), contents.to_string());
+        self
+    }
+
+    pub fn with_diff(mut self, path: &str, diff: &str) -> Self {
+        self.diffs.insert(path.to_string(), diff.to_string());
+        self
+    }
+
+    /// Sets the coloured `diff_external` output of a path; it defaults to the plain diff.
+    pub fn with_external_diff(mut self, path: &str, diff: &str) -> Self {
+        self.external_diffs
+            .insert(path.to_string(), diff.to_string());
+        self
+    }
+
+    /// Sets the source path of a target; it defaults to `/source/<path>`.
+    pub fn with_source_path(mut self, path: &str, source_path: &str) -> Self {
+        self.source_paths
+            .insert(path.to_string(), source_path.to_string());
+        self
+    }
+
+    /// Sets the output of a git command, keyed by its space-separated arguments (e.g.
+    /// `"status --porcelain -- /source/dot_bashrc"`).
+    pub fn with_git(mut self, args: &str, output: &str) -> Self {
+        self.git_outputs
+            .insert(args.to_string(), output.to_string());
+        self
+    }
+
+    /// Runs git for real in `dir`, e.g. a scratch repository, instead of returning the
+    /// outputs set with [`Self::with_git`].
+    pub fn with_git_dir(mut self, dir: &std::path::Path) -> Self {
+        self.git_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Sets the contents of a file read and written by absolute path.
+    pub fn with_file(self, path: &str, contents: &str) -> Self {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.to_string());
+        self
+    }
+
+    /// Contents of a file written by absolute path, or set with [`Self::with_file`].
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.files.lock().unwrap().get(path).cloned()
+    }
+
+    /// Makes every `apply` fail with the given stderr.
+    pub fn with_apply_error(mut self, stderr: &str) -> Self {
+        self.apply_error = Some(stderr.to_string());
+        self
+    }
+
+    /// Makes every `re_add` fail with the given stderr.
+    pub fn with_re_add_error(mut self, stderr: &str) -> Self {
+        self.re_add_error = Some(stderr.to_string());
+        self
+    }
+
+    /// Makes every apply or re-add that includes `path` fail with the given stderr.
+    pub fn with_path_error(mut self, path: &str, stderr: &str) -> Self {
+        self.path_errors
+            .insert(path.to_string(), stderr.to_string());
+        self
+    }
+
+    /// Returns the calls made so far, formatted like the chezmoi command line.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, command: &str, args: &[String]) {
+        let mut call = command.to_string();
+        for arg in args {
+            call.push(' ');
+            call.push_str(arg);
+        }
+        self.calls.lock().unwrap().push(call);
+    }
+
+    fn path_error(&self, paths: &[String]) -> ChezmoiResult<()> {
+        match paths.iter().find_map(|path| self.path_errors.get(path)) {
+            Some(stderr) => Err(ChezmoiError::from_exit("chezmoi", Some(1), stderr)),
+            None => Ok(()),
+        }
+    }
+
+    fn sync(&self, paths: &[String]) {
+        self.status.lock().unwrap().retain(|line| {
+            utils::extract_filename_and_status(line)
+                .map_or(true, |(path, _, _)| !paths.contains(&path))
+        });
+    }
 }
 
-pub fn edit(highlighted_file: String) {
-    let _ = Command::new("chezmoi")
-        .arg("edit")
-        .arg(format!("{}{}", HOME, highlighted_file))
-        .spawn()
-        .unwrap_or_else(|_| panic!("failed to execute chezmoi edit"))
-        .wait();
+impl ChezmoiBackend for ScriptedBackend {
+    fn status(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<FileItem>> {
+        self.record("status", &types.args());
+        if let Some(stderr) = &self.status_error {
+            return Err(ChezmoiError::from_exit("chezmoi", Some(1), stderr));
+        }
+        let mut files = parse_status(&self.status.lock().unwrap().join("\n"))?;
+        files.retain(|file| {
+            let entry_types = self
+                .entry_types
+                .get(&file.path)
+                .map_or(&[EntryType::Files][..], Vec::as_slice);
+            types.matches(entry_types)
+        });
+        Ok(files)
+    }
+
+    fn diff(&self, path: &str) -> ChezmoiResult<String> {
+        self.record("diff", &[path.to_string()]);
+        Ok(self.diffs.get(path).cloned().unwrap_or_default())
+    }
+
+    fn diff_external(&self, path: &str) -> ChezmoiResult<String> {
+        self.record("diff", &["--color=true".to_string(), path.to_string()]);
+        Ok(self
+            .external_diffs
+            .get(path)
+            .or(self.diffs.get(path))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn diff_all(&self) -> ChezmoiResult<String> {
+        self.record("diff", &[]);
+        let mut paths: Vec<&String> = self.diffs.keys().collect();
+        paths.sort();
+        let mut output = String::new();
+        for path in paths {
+            let diff = &self.diffs[path];
+            if !diff.starts_with("diff --git ") {
+                output.push_str(&format!("diff --git a/{} b/{}\n", path, path));
+            }
+            output.push_str(diff);
+            if !diff.ends_with('\n') {
+                output.push('\n');
+            }
+        }
+        Ok(output)
+    }
+
+    fn re_add(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        self.record("re-add", selected_files);
+        if let Some(stderr) = &self.re_add_error {
+            return Err(ChezmoiError::from_exit("chezmoi", Some(1), stderr));
+        }
+        self.path_error(selected_files)?;
+        self.sync(selected_files);
+        Ok(())
+    }
+
+    fn apply(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        self.record("apply", selected_files);
+        if let Some(stderr) = &self.apply_error {
+            return Err(ChezmoiError::from_exit("chezmoi", Some(1), stderr));
+        }
+        self.path_error(selected_files)?;
+        self.sync(selected_files);
+        Ok(())
+    }
+
+    fn force_apply(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut args = vec!["--force".to_string()];
+        args.extend_from_slice(selected_files);
+        self.record("apply", &args);
+        if let Some(stderr) = &self.apply_error {
+            return Err(ChezmoiError::from_exit("chezmoi", Some(1), stderr));
+        }
+        self.path_error(selected_files)?;
+        self.sync(selected_files);
+        Ok(())
+    }
+
+    fn dry_run_apply(&self, selected_files: &[String]) -> ChezmoiResult<String> {
+        let mut args = vec!["--dry-run".to_string(), "--verbose".to_string()];
+        args.extend_from_slice(selected_files);
+        self.record("apply", &args);
+        let mut output = String::new();
+        for path in selected_files {
+            let Some(diff) = self.diffs.get(path) else {
+                continue;
+            };
+            if !diff.starts_with("diff --git ") {
+                output.push_str(&format!("diff --git a/{} b/{}\n", path, path));
+            }
+            output.push_str(diff);
+            if !diff.ends_with('\n') {
+                output.push('\n');
+            }
+        }
+        Ok(output)
+    }
+
+    fn edit(&self, highlighted_file: String) -> ChezmoiResult<()> {
+        self.record("edit", &[highlighted_file]);
+        Ok(())
+    }
+
+    fn open_source(&self) -> ChezmoiResult<()> {
+        self.record("edit", &[]);
+        Ok(())
+    }
+
+    fn open_git_ui(&self, command: &[String]) -> ChezmoiResult<()> {
+        self.record("git-ui", command);
+        Ok(())
+    }
+
+    fn merge(&self, path: &str) -> ChezmoiResult<()> {
+        self.record("merge", &[path.to_string()]);
+        Ok(())
+    }
+
+    fn managed(&self, types: &EntryTypeFilter) -> ChezmoiResult<Vec<String>> {
+        self.record("managed", &types.args());
+        let mut paths = self.managed.lock().unwrap().clone();
+        paths.retain(|path| {
+            let entry_types = self
+                .entry_types
+                .get(path)
+                .map_or(&[EntryType::Files][..], Vec::as_slice);
+            types.matches(entry_types)
+        });
+        Ok(paths)
+    }
+
+    fn cat(&self, path: &str) -> ChezmoiResult<String> {
+        self.record("cat", &[path.to_string()]);
+        Ok(self.contents.get(path).cloned().unwrap_or_default())
+    }
+
+    fn forget(&self, selected_files: &[String]) -> ChezmoiResult<()> {
+        self.record("forget", selected_files);
+        self.managed
+            .lock()
+            .unwrap()
+            .retain(|path| !selected_files.contains(path));
+        self.sync(selected_files);
+        Ok(())
+    }
+
+    fn chattr(&self, attributes: &str, selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut args = vec![attributes.to_string()];
+        args.extend_from_slice(selected_files);
+        self.record("chattr", &args);
+        Ok(())
+    }
+
+    fn unmanaged(&self, dir: &str) -> ChezmoiResult<Vec<String>> {
+        self.record("unmanaged", &[dir.to_string()]);
+        Ok(self
+            .unmanaged
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|path| path.starts_with(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn read_target(&self, path: &str) -> ChezmoiResult<String> {
+        Ok(self.contents.get(path).cloned().unwrap_or_default())
+    }
+
+    fn add(&self, options: &[AddOption], selected_files: &[String]) -> ChezmoiResult<()> {
+        let mut args: Vec<String> = options.iter().map(|o| o.flag().to_string()).collect();
+        args.extend_from_slice(selected_files);
+        self.record("add", &args);
+        self.unmanaged
+            .lock()
+            .unwrap()
+            .retain(|path| !selected_files.contains(path));
+        self.managed
+            .lock()
+            .unwrap()
+            .extend(selected_files.iter().cloned());
+        Ok(())
+    }
+
+    fn target_path(&self, path: &str) -> String {
+        format!("/home/{}", path)
+    }
+
+    fn source_path(&self, path: &str) -> ChezmoiResult<String> {
+        Ok(self
+            .source_paths
+            .get(path)
+            .cloned()
+            .unwrap_or_else(|| format!("/source/{}", path)))
+    }
+
+    fn git(&self, args: &[&str]) -> ChezmoiResult<String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        self.record("git", &args);
+        if let Some(dir) = &self.git_dir {
+            let output = run(Command::new("git").arg("-C").arg(dir).args(&args))?;
+            return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+        }
+        Ok(self
+            .git_outputs
+            .get(&args.join(" "))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn read_file(&self, path: &str) -> ChezmoiResult<String> {
+        self.file(path).ok_or_else(|| ChezmoiError::Io {
+            path: path.to_string(),
+            source: std::io::ErrorKind::NotFound.into(),
+        })
+    }
+
+    fn write_file(&self, path: &str, contents: &str) -> ChezmoiResult<()> {
+        self.record("write", &[path.to_string()]);
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.to_string());
+        Ok(())
+    }
 }
 
-pub fn open_source() {
-    let _ = Command::new("chezmoi")
-        .arg("edit")
-        .spawn()
-        .unwrap_or_else(|_| panic!("failed to execute chezmoi edit"))
-        .wait();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_program_is_named_alone() {
+        let command = ["lazychezmoi-missing-git-ui", "-p", "{source}"].map(String::from);
+        let error = run_interactive(Command::new(&command[0]).args(&command[1..])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to execute lazychezmoi-missing-git-ui: not found in PATH"
+        );
+    }
+
+    #[test]
+    fn failing_program_is_named() {
+        let error = run_interactive(&mut Command::new("false")).unwrap_err();
+        assert_eq!(error.to_string(), "false exited with code 1");
+        let error = run(Command::new("sh").args(["-c", "echo oops >&2; exit 3"])).unwrap_err();
+        assert_eq!(error.to_string(), "sh exited with code 3: oops");
+    }
+
+    #[test]
+    fn scripted_status_follows_the_entry_type_filter() {
+        let backend = ScriptedBackend::new()
+            .with_status(&[" M .bashrc", " R .local/bin/install.sh"])
+            .with_entry_types(".local/bin/install.sh", &[EntryType::Scripts]);
+        let scripts = EntryTypeFilter {
+            include: vec![EntryType::Scripts],
+            exclude: Vec::new(),
+        };
+        let paths =
+            |files: Vec<FileItem>| -> Vec<String> { files.into_iter().map(|f| f.path).collect() };
+        assert_eq!(
+            paths(backend.status(&scripts).unwrap()),
+            [".local/bin/install.sh"]
+        );
+        assert_eq!(
+            paths(backend.status(&EntryTypeFilter::default()).unwrap()),
+            [".bashrc", ".local/bin/install.sh"]
+        );
+        assert_eq!(backend.calls(), ["status --include=scripts", "status"]);
+    }
+
+    #[test]
+    fn scripted_apply_and_re_add_bring_files_in_sync() {
+        let backend = ScriptedBackend::new()
+            .with_status(&["M  .bashrc", " M .zshrc", " M .vimrc"])
+            .with_path_error(".vimrc", "chezmoi: .vimrc: permission denied");
+        backend.re_add(&[".bashrc".to_string()]).unwrap();
+        backend.apply(&[".zshrc".to_string()]).unwrap();
+        let error = backend.apply(&[".vimrc".to_string()]).unwrap_err();
+        assert!(error.to_string().contains("permission denied"));
+        let files = backend.status(&EntryTypeFilter::default()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, ".vimrc");
+        assert_eq!(
+            backend.calls(),
+            ["re-add .bashrc", "apply .zshrc", "apply .vimrc", "status"]
+        );
+    }
+
+    #[test]
+    fn scripted_errors_fail_every_call() {
+        let backend = ScriptedBackend::new()
+            .with_status_error("chezmoi: invalid config")
+            .with_apply_error("chezmoi: .bashrc: has changed since chezmoi last wrote it");
+        assert!(backend.status(&EntryTypeFilter::default()).is_err());
+        assert!(backend.apply(&[".bashrc".to_string()]).is_err());
+        assert!(backend.re_add(&[".bashrc".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn scripted_diff_all_splits_back_into_file_diffs() {
+        let backend = ScriptedBackend::new()
+            .with_diff(".bashrc", "@@ -1 +1 @@\n-a\n+b\n")
+            .with_diff(
+                ".zshrc",
+                "diff --git a/.zshrc b/.zshrc\n@@ -1 +1 @@\n-c\n+d",
+            );
+        let diffs = split_diff(&backend.diff_all().unwrap());
+        assert_eq!(
+            diffs[".bashrc"],
+            "diff --git a/.bashrc b/.bashrc\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+        assert_eq!(
+            diffs[".zshrc"],
+            "diff --git a/.zshrc b/.zshrc\n@@ -1 +1 @@\n-c\n+d\n"
+        );
+    }
 }
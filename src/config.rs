@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use serde::Deserialize;
+
+/// User settings, read from `$XDG_CONFIG_HOME/lazychezmoi/config.toml` (or
+/// `~/.config/lazychezmoi/config.toml`). Every setting is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub diff: DiffConfig,
+    pub git_ui: GitUiConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffConfig {
+    /// Show the colours of `chezmoi diff` as configured in chezmoi (`diff.pager`,
+    /// `diff.command`, e.g. delta or difftastic) instead of the built-in colouring.
+    pub external: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitUiConfig {
+    /// Program and arguments of the git UI opened on the source directory, where
+    /// `{source}` stands for the directory. It also runs from there.
+    pub command: Vec<String>,
+}
+
+impl Default for GitUiConfig {
+    fn default() -> Self {
+        Self {
+            command: ["lazygit", "-p", "{source}"].map(String::from).to_vec(),
+        }
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("lazychezmoi").join("config.toml"))
+}
+
+/// Reads the config file, falling back to the defaults when there is none.
+pub fn load() -> Result<Config> {
+    let Some(path) = path().filter(|path| path.exists()) else {
+        return Ok(Config::default());
+    };
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&contents).wrap_err_with(|| format!("invalid config in {}", path.display()))
+}
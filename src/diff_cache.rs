@@ -0,0 +1,71 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::worker::PreviewKind;
+
+/// Per-file previews already fetched from chezmoi, by path and kind: the same path can be
+/// shown as a diff in one view and as its contents in another.
+///
+/// Every invalidation bumps the epoch; results computed under an older epoch describe a state
+/// that no longer exists and are refused by [`DiffCache::insert`].
+#[derive(Debug, Default)]
+pub struct DiffCache {
+    entries: HashMap<(String, PreviewKind), String>,
+    /// Diffs coloured by an external diff tool, shown instead of the plain ones.
+    rendered: HashMap<String, String>,
+    pending: HashSet<(String, PreviewKind)>,
+    epoch: u64,
+}
+
+impl DiffCache {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn get(&self, path: &str, kind: PreviewKind) -> Option<&String> {
+        self.entries.get(&(path.to_string(), kind))
+    }
+
+    /// Whether `path` is neither cached nor already being fetched as `kind`.
+    pub fn is_missing(&self, path: &str, kind: PreviewKind) -> bool {
+        let key = (path.to_string(), kind);
+        !self.entries.contains_key(&key) && !self.pending.contains(&key)
+    }
+
+    pub fn mark_pending(&mut self, path: &str, kind: PreviewKind) {
+        self.pending.insert((path.to_string(), kind));
+    }
+
+    /// Forgets that `path` is being fetched, e.g. because the job was skipped or failed.
+    pub fn clear_pending(&mut self, path: &str, kind: PreviewKind) {
+        self.pending.remove(&(path.to_string(), kind));
+    }
+
+    /// Stores a preview, returning `false` if it was computed before the last invalidation.
+    pub fn insert(&mut self, path: String, kind: PreviewKind, epoch: u64, text: String) -> bool {
+        if epoch != self.epoch {
+            return false;
+        }
+        let key = (path, kind);
+        self.pending.remove(&key);
+        self.entries.insert(key, text);
+        true
+    }
+
+    pub fn rendered(&self, path: &str) -> Option<&String> {
+        self.rendered.get(path)
+    }
+
+    /// Stores the coloured version of a diff, under the same rule as [`DiffCache::insert`].
+    pub fn insert_rendered(&mut self, path: String, epoch: u64, rendered: String) {
+        if epoch == self.epoch {
+            self.rendered.insert(path, rendered);
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+        self.rendered.clear();
+        self.pending.clear();
+        self.epoch += 1;
+    }
+}
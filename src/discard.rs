@@ -0,0 +1,268 @@
+use crate::chezmoi::{ChezmoiBackend, ChezmoiError, ChezmoiResult};
+use crate::hunks::{self, PatchSide};
+
+/// Which side of a change gets thrown away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscardSide {
+    /// The target was modified: restore it from the source state.
+    Local,
+    /// The source state was modified: reset it to the last commit or to the target.
+    Source,
+}
+
+impl DiscardSide {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DiscardSide::Local => "local",
+            DiscardSide::Source => "source",
+        }
+    }
+
+    /// Prefix of the diff lines that are lost.
+    pub fn lost_prefix(&self) -> char {
+        match self {
+            DiscardSide::Local => '-',
+            DiscardSide::Source => '+',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscardMethod {
+    /// `chezmoi apply --force` on the target.
+    ForceApply,
+    /// `git checkout HEAD` of the source file, which has uncommitted changes. Staged changes
+    /// are reset as well.
+    GitCheckout { source_path: String },
+    /// `chezmoi re-add` of the target, for committed source changes.
+    ReAdd,
+    /// Only the given hunks of `diff` are reverted in the target.
+    PatchTarget { diff: String, hunks: Vec<usize> },
+}
+
+impl DiscardMethod {
+    pub fn description(&self) -> String {
+        match self {
+            DiscardMethod::ForceApply => "chezmoi apply --force".to_string(),
+            DiscardMethod::GitCheckout { source_path } => {
+                format!("chezmoi git -- checkout HEAD -- {}", source_path)
+            }
+            DiscardMethod::ReAdd => "chezmoi re-add".to_string(),
+            DiscardMethod::PatchTarget { hunks, .. } => {
+                format!(
+                    "patch the target with {} hunk(s) of the source state",
+                    hunks.len()
+                )
+            }
+        }
+    }
+}
+
+/// Everything the confirmation modal shows before a discard runs.
+#[derive(Debug, Clone)]
+pub struct DiscardPlan {
+    pub path: String,
+    pub side: DiscardSide,
+    pub method: DiscardMethod,
+    /// The changes that will be thrown away.
+    pub diff: String,
+}
+
+/// Works out how to discard one side of the changes of `path`, without touching anything.
+///
+/// Local changes are always discarded by re-applying the source state, which renders
+/// templates and decrypts encrypted files like a normal apply. Source changes are undone
+/// with git when the source file has uncommitted changes; otherwise the target is re-added,
+/// which chezmoi re-encrypts for encrypted files but which would flatten a template into its
+/// rendered output, so that case is refused. A source file added or renamed in the index has
+/// no version in `HEAD` to go back to, so it is refused too.
+pub fn plan(
+    backend: &dyn ChezmoiBackend,
+    path: &str,
+    side: DiscardSide,
+) -> ChezmoiResult<DiscardPlan> {
+    if side == DiscardSide::Local {
+        return Ok(DiscardPlan {
+            path: path.to_string(),
+            side,
+            method: DiscardMethod::ForceApply,
+            diff: backend.diff(path)?,
+        });
+    }
+
+    let source_path = backend.source_path(path)?;
+    let file_name = source_path.rsplit('/').next().unwrap_or_default();
+    let is_template = file_name.ends_with(".tmpl");
+    let is_encrypted = file_name.starts_with("encrypted_");
+
+    let git_status = backend.git(&["status", "--porcelain", "--", &source_path])?;
+    // `XY path` lines: X is the index, Y the working tree, `??` an untracked file
+    let index_codes: Vec<char> = git_status
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("??"))
+        .filter_map(|line| line.chars().next())
+        .collect();
+    if index_codes
+        .iter()
+        .any(|code| matches!(code, 'A' | 'R' | 'C'))
+    {
+        return Err(ChezmoiError::Unsupported(format!(
+            "{} is new in the source repository and not committed yet: there is no committed version to restore, unstage it first",
+            path
+        )));
+    }
+    let has_uncommitted_changes = !index_codes.is_empty();
+
+    let (method, diff) = if has_uncommitted_changes {
+        // The git diff of an encrypted file is ciphertext, chezmoi's is readable
+        let diff = if is_encrypted {
+            backend.diff(path)?
+        } else {
+            backend.git(&["diff", "HEAD", "--", &source_path])?
+        };
+        (DiscardMethod::GitCheckout { source_path }, diff)
+    } else if is_template {
+        return Err(ChezmoiError::Unsupported(format!(
+            "{} is a template without uncommitted changes: restoring it from the target would replace the template with its rendered output",
+            path
+        )));
+    } else {
+        (DiscardMethod::ReAdd, backend.diff(path)?)
+    };
+
+    Ok(DiscardPlan {
+        path: path.to_string(),
+        side,
+        method,
+        diff,
+    })
+}
+
+/// Discarding some local hunks of `path` only: nothing needs to be asked to chezmoi
+/// beforehand.
+pub fn plan_hunks(path: &str, diff: &str, hunks: Vec<usize>) -> DiscardPlan {
+    DiscardPlan {
+        path: path.to_string(),
+        side: DiscardSide::Local,
+        diff: hunks::hunks_text(diff, &hunks),
+        method: DiscardMethod::PatchTarget {
+            diff: diff.to_string(),
+            hunks,
+        },
+    }
+}
+
+pub fn execute(backend: &dyn ChezmoiBackend, plan: &DiscardPlan) -> ChezmoiResult<()> {
+    let paths = [plan.path.clone()];
+    match &plan.method {
+        DiscardMethod::ForceApply => backend.force_apply(&paths),
+        DiscardMethod::GitCheckout { source_path } => backend
+            .git(&["checkout", "HEAD", "--", source_path])
+            .map(|_| ()),
+        DiscardMethod::ReAdd => backend.re_add(&paths),
+        DiscardMethod::PatchTarget { diff, hunks } => {
+            hunks::patch_file(backend, &plan.path, diff, hunks, PatchSide::Target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chezmoi::ScriptedBackend;
+
+    const DIFF: &str = "diff --git a/.bashrc b/.bashrc\n@@ -1 +1 @@\n-local\n+source\n";
+    const GIT_DIFF: &str =
+        "diff --git a/dot_bashrc b/dot_bashrc\n@@ -1 +1 @@\n-committed\n+source\n";
+
+    fn backend(source_path: &str, porcelain: &str) -> ScriptedBackend {
+        ScriptedBackend::new()
+            .with_source_path(".bashrc", source_path)
+            .with_diff(".bashrc", DIFF)
+            .with_git(&format!("status --porcelain -- {}", source_path), porcelain)
+            .with_git(&format!("diff HEAD -- {}", source_path), GIT_DIFF)
+    }
+
+    #[test]
+    fn local_changes_are_force_applied() {
+        let backend = backend("/source/dot_bashrc", "");
+        let plan = plan(&backend, ".bashrc", DiscardSide::Local).unwrap();
+        assert_eq!(plan.method, DiscardMethod::ForceApply);
+        assert_eq!(plan.diff, DIFF);
+        execute(&backend, &plan).unwrap();
+        assert_eq!(backend.calls().last().unwrap(), "apply --force .bashrc");
+    }
+
+    #[test]
+    fn uncommitted_source_changes_are_checked_out() {
+        for porcelain in [" M dot_bashrc\n", "M  dot_bashrc\n", "MM dot_bashrc\n"] {
+            let backend = backend("/source/dot_bashrc", porcelain);
+            let plan = plan(&backend, ".bashrc", DiscardSide::Source).unwrap();
+            assert_eq!(
+                plan.method,
+                DiscardMethod::GitCheckout {
+                    source_path: "/source/dot_bashrc".to_string()
+                },
+                "{}",
+                porcelain
+            );
+            assert_eq!(plan.diff, GIT_DIFF);
+            execute(&backend, &plan).unwrap();
+            assert_eq!(
+                backend.calls().last().unwrap(),
+                "git checkout HEAD -- /source/dot_bashrc"
+            );
+        }
+    }
+
+    #[test]
+    fn encrypted_files_show_chezmoi_diff() {
+        let backend = backend(
+            "/source/encrypted_dot_bashrc.age",
+            " M encrypted_dot_bashrc.age\n",
+        );
+        let plan = plan(&backend, ".bashrc", DiscardSide::Source).unwrap();
+        assert!(matches!(plan.method, DiscardMethod::GitCheckout { .. }));
+        assert_eq!(plan.diff, DIFF);
+    }
+
+    #[test]
+    fn committed_source_changes_are_re_added() {
+        for porcelain in ["", "?? dot_bashrc\n"] {
+            let backend = backend("/source/dot_bashrc", porcelain);
+            let plan = plan(&backend, ".bashrc", DiscardSide::Source).unwrap();
+            assert_eq!(plan.method, DiscardMethod::ReAdd);
+            assert_eq!(plan.diff, DIFF);
+        }
+    }
+
+    #[test]
+    fn committed_templates_are_refused() {
+        let backend = backend("/source/dot_bashrc.tmpl", "");
+        assert!(matches!(
+            plan(&backend, ".bashrc", DiscardSide::Source),
+            Err(ChezmoiError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn sources_missing_from_head_are_refused() {
+        for porcelain in [
+            "A  dot_bashrc\n",
+            "AM dot_bashrc\n",
+            "R  dot_profile -> dot_bashrc\n",
+        ] {
+            let backend = backend("/source/dot_bashrc", porcelain);
+            let error = plan(&backend, ".bashrc", DiscardSide::Source).unwrap_err();
+            assert!(
+                error.to_string().contains("not committed yet"),
+                "{}",
+                porcelain
+            );
+            assert!(!backend
+                .calls()
+                .iter()
+                .any(|c| c.starts_with("git checkout")));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use crate::app::FileItem;
+use crate::chezmoi::{EntryType, EntryTypeFilter};
+use crate::utils::FileStatus;
+
+/// Client-side filters on the status columns of `chezmoi status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusFilter {
+    /// Changed in the target, i.e. re-add candidates.
+    Local,
+    /// Changed in the source state, i.e. apply candidates.
+    Source,
+    Deleted,
+    Scripts,
+}
+
+impl StatusFilter {
+    pub const ALL: [StatusFilter; 4] = [
+        StatusFilter::Local,
+        StatusFilter::Source,
+        StatusFilter::Deleted,
+        StatusFilter::Scripts,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatusFilter::Local => "local",
+            StatusFilter::Source => "source",
+            StatusFilter::Deleted => "deleted",
+            StatusFilter::Scripts => "scripts",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            StatusFilter::Local => "Locally modified (re-add candidates)",
+            StatusFilter::Source => "Source changes (apply candidates)",
+            StatusFilter::Deleted => "Deletions",
+            StatusFilter::Scripts => "Scripts",
+        }
+    }
+
+    fn matches(&self, file: &FileItem) -> bool {
+        match self {
+            StatusFilter::Local => file.local_status != FileStatus::Unchanged,
+            StatusFilter::Source => file.source_status != FileStatus::Unchanged,
+            StatusFilter::Deleted => {
+                file.local_status == FileStatus::Deleted
+                    || file.source_status == FileStatus::Deleted
+            }
+            StatusFilter::Scripts => file.is_script(),
+        }
+    }
+}
+
+/// One line of the filter popup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterItem {
+    Status(StatusFilter),
+    Type(EntryType),
+}
+
+/// Every filter applied to the status list on top of the fuzzy query.
+#[derive(Debug, Default)]
+pub struct Filters {
+    pub status: HashSet<StatusFilter>,
+    /// Applied by chezmoi itself, so changing it needs a status refresh.
+    pub types: EntryTypeFilter,
+}
+
+impl Filters {
+    pub fn items() -> Vec<FilterItem> {
+        StatusFilter::ALL
+            .into_iter()
+            .map(FilterItem::Status)
+            .chain(EntryType::ALL.into_iter().map(FilterItem::Type))
+            .collect()
+    }
+
+    /// Whether a file passes the status filters: selected filters add up, so the file has
+    /// to match any one of them. Nothing selected lets everything through.
+    pub fn matches(&self, file: &FileItem) -> bool {
+        self.status.is_empty() || self.status.iter().any(|filter| filter.matches(file))
+    }
+
+    pub fn toggle_status(&mut self, filter: StatusFilter) {
+        if !self.status.remove(&filter) {
+            self.status.insert(filter);
+        }
+    }
+
+    /// Cycles an entry type through neutral -> included -> excluded.
+    pub fn cycle_type(&mut self, entry_type: EntryType) {
+        if let Some(i) = self.types.include.iter().position(|t| *t == entry_type) {
+            self.types.include.remove(i);
+            self.types.exclude.push(entry_type);
+        } else if let Some(i) = self.types.exclude.iter().position(|t| *t == entry_type) {
+            self.types.exclude.remove(i);
+        } else {
+            self.types.include.push(entry_type);
+        }
+    }
+
+    /// Checkbox and text of a popup line.
+    pub fn item_label(&self, item: FilterItem) -> String {
+        match item {
+            FilterItem::Status(filter) => format!(
+                "[{}] {}",
+                if self.status.contains(&filter) {
+                    "x"
+                } else {
+                    " "
+                },
+                filter.description()
+            ),
+            FilterItem::Type(entry_type) => {
+                let mark = if self.types.include.contains(&entry_type) {
+                    "+"
+                } else if self.types.exclude.contains(&entry_type) {
+                    "-"
+                } else {
+                    " "
+                };
+                format!("[{}] Type: {}", mark, entry_type.as_str())
+            }
+        }
+    }
+
+    /// Short summary of the active filters for the status pane title, empty if none.
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<String> = StatusFilter::ALL
+            .iter()
+            .filter(|f| self.status.contains(f))
+            .map(|f| f.label().to_string())
+            .collect();
+        parts.extend(
+            self.types
+                .include
+                .iter()
+                .map(|t| format!("+{}", t.as_str())),
+        );
+        parts.extend(
+            self.types
+                .exclude
+                .iter()
+                .map(|t| format!("-{}", t.as_str())),
+        );
+        if parts.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", parts.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Selection;
+
+    fn file(local_status: FileStatus, source_status: FileStatus) -> FileItem {
+        FileItem {
+            path: "file".to_string(),
+            selected: Selection::None,
+            local_status,
+            source_status,
+        }
+    }
+
+    fn filters(status: &[StatusFilter]) -> Filters {
+        Filters {
+            status: status.iter().copied().collect(),
+            types: EntryTypeFilter::default(),
+        }
+    }
+
+    #[test]
+    fn no_status_filter_matches_everything() {
+        assert!(filters(&[]).matches(&file(FileStatus::Unchanged, FileStatus::Unchanged)));
+    }
+
+    #[test]
+    fn status_filters_add_up() {
+        let files = [
+            file(FileStatus::Modified, FileStatus::Unchanged),
+            file(FileStatus::Unchanged, FileStatus::Modified),
+            file(FileStatus::Modified, FileStatus::Modified),
+            file(FileStatus::Unchanged, FileStatus::Unchanged),
+        ];
+        let filters = filters(&[StatusFilter::Local, StatusFilter::Source]);
+        let matched: Vec<bool> = files.iter().map(|f| filters.matches(f)).collect();
+        assert_eq!(matched, [true, true, true, false]);
+    }
+
+    #[test]
+    fn deleted_and_scripts_match_either() {
+        let filters = filters(&[StatusFilter::Deleted, StatusFilter::Scripts]);
+        assert!(filters.matches(&file(FileStatus::Deleted, FileStatus::Unchanged)));
+        assert!(filters.matches(&file(FileStatus::Unchanged, FileStatus::Run)));
+        assert!(!filters.matches(&file(FileStatus::Modified, FileStatus::Unchanged)));
+    }
+}
@@ -0,0 +1,15 @@
+/// Matches `query` as a case-insensitive subsequence of `text`.
+///
+/// Returns the byte offsets in `text` of the matched characters, or `None` when some query
+/// character can't be found in order. An empty query matches everything.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Vec<usize>> {
+    let mut positions = Vec::new();
+    let mut text_chars = text.char_indices();
+    for query_char in query.chars().filter(|c| !c.is_whitespace()) {
+        let (position, _) = text_chars
+            .by_ref()
+            .find(|(_, c)| c.to_lowercase().eq(query_char.to_lowercase()))?;
+        positions.push(position);
+    }
+    Some(positions)
+}
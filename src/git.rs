@@ -0,0 +1,353 @@
+use crate::chezmoi::{ChezmoiBackend, ChezmoiError, ChezmoiResult};
+
+/// One changed path of the source directory, as reported by `git status`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitEntry {
+    /// Path relative to the source directory.
+    pub path: String,
+    /// Status codes in the index and in the working tree, e.g. `M`, `A`, `D`, `?` or `.`
+    /// when unchanged, as printed by `git status --porcelain=v2`.
+    pub index: char,
+    pub worktree: char,
+}
+
+impl GitEntry {
+    pub fn is_untracked(&self) -> bool {
+        self.index == '?'
+    }
+
+    /// Has changes in the index, which the next commit includes.
+    pub fn is_staged(&self) -> bool {
+        !matches!(self.index, '.' | '?')
+    }
+
+    /// Has changes that `git add` would stage.
+    pub fn has_unstaged_changes(&self) -> bool {
+        self.worktree != '.'
+    }
+}
+
+/// State of the source repository shown by the git panel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitStatus {
+    /// Current branch, `None` on a detached HEAD.
+    pub branch: Option<String>,
+    /// Branch tracked by the current one, e.g. `origin/main`.
+    pub upstream: Option<String>,
+    /// Commits not pushed to and not pulled from the upstream, as of the last fetch.
+    pub ahead: usize,
+    pub behind: usize,
+    pub entries: Vec<GitEntry>,
+}
+
+impl GitStatus {
+    pub fn staged_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_staged()).count()
+    }
+}
+
+/// A commit brought in by an update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    /// Abbreviated hash.
+    pub hash: String,
+    pub author: String,
+    /// Relative date, e.g. `2 hours ago`.
+    pub date: String,
+    pub subject: String,
+}
+
+/// A git command changing the source repository, run with `chezmoi git -- <args>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GitCommand {
+    Stage(Vec<String>),
+    Unstage(Vec<String>),
+    Commit(String),
+    Push,
+    Pull,
+}
+
+impl GitCommand {
+    pub fn args(&self) -> Vec<String> {
+        let with_paths = |args: &[&str], paths: &[String]| {
+            let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            args.push("--".to_string());
+            args.extend_from_slice(paths);
+            args
+        };
+        match self {
+            GitCommand::Stage(paths) => with_paths(&["add"], paths),
+            // Unlike `restore --staged`, works before the first commit
+            GitCommand::Unstage(paths) => with_paths(&["reset", "--quiet"], paths),
+            GitCommand::Commit(message) => {
+                vec!["commit".to_string(), "-m".to_string(), message.clone()]
+            }
+            GitCommand::Push => vec!["push".to_string()],
+            // A merge commit gets git's default message, no editor can be opened
+            GitCommand::Pull => vec!["pull".to_string(), "--no-edit".to_string()],
+        }
+    }
+
+    /// Shown while the command runs.
+    pub fn progress_label(&self) -> String {
+        match self {
+            GitCommand::Stage(paths) => format!("Staging {} path(s)", paths.len()),
+            GitCommand::Unstage(paths) => format!("Unstaging {} path(s)", paths.len()),
+            GitCommand::Commit(_) => "Committing".to_string(),
+            GitCommand::Push => "Pushing".to_string(),
+            GitCommand::Pull => "Pulling".to_string(),
+        }
+    }
+}
+
+pub fn status(backend: &dyn ChezmoiBackend) -> ChezmoiResult<GitStatus> {
+    let output = backend.git(&["status", "--porcelain=v2", "--branch", "-z"])?;
+    parse_status(&output)
+}
+
+pub fn run(backend: &dyn ChezmoiBackend, command: &GitCommand) -> ChezmoiResult<()> {
+    let args = command.args();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    backend.git(&args).map(|_| ())
+}
+
+/// Pulls the source repository without applying anything, the first half of
+/// `chezmoi update`, and returns the commits it brought in, newest first.
+pub fn update(backend: &dyn ChezmoiBackend) -> ChezmoiResult<Vec<Commit>> {
+    // There is no HEAD before the first commit
+    let old_head = backend
+        .git(&["rev-parse", "HEAD"])
+        .ok()
+        .map(|head| head.trim().to_string())
+        .filter(|head| !head.is_empty());
+    run(backend, &GitCommand::Pull)?;
+    let range = match old_head {
+        Some(head) => format!("{}..HEAD", head),
+        None => "HEAD".to_string(),
+    };
+    let log = backend.git(&["log", "--format=%h%x09%an%x09%ar%x09%s", &range])?;
+    Ok(log
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            Some(Commit {
+                hash: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                subject: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Parses `git status --porcelain=v2 --branch -z`: `# branch.*` headers, then one
+/// NUL-terminated record per changed path.
+fn parse_status(output: &str) -> ChezmoiResult<GitStatus> {
+    let mut status = GitStatus::default();
+    let mut records = output.split('\0').filter(|record| !record.is_empty());
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.head" if value != "(detached)" => status.branch = Some(value.to_string()),
+                "branch.upstream" => status.upstream = Some(value.to_string()),
+                "branch.ab" => {
+                    for count in value.split(' ') {
+                        if let Some(n) = count.strip_prefix('+') {
+                            status.ahead = n.parse().unwrap_or_default();
+                        } else if let Some(n) = count.strip_prefix('-') {
+                            status.behind = n.parse().unwrap_or_default();
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        // Fields before the path: ordinary, renamed or copied, and unmerged entries
+        let fields = match record.chars().next() {
+            Some('1') => 8,
+            Some('2') => 9,
+            Some('u') => 10,
+            Some('?') => {
+                status.entries.push(GitEntry {
+                    path: record[2..].to_string(),
+                    index: '?',
+                    worktree: '?',
+                });
+                continue;
+            }
+            // Ignored files are only listed on request
+            Some('!') => continue,
+            _ => {
+                return Err(ChezmoiError::Parse(format!(
+                    "unexpected git status record: {:?}",
+                    record
+                )))
+            }
+        };
+        let mut parts = record.splitn(fields + 1, ' ');
+        let codes: Vec<char> = parts.nth(1).unwrap_or_default().chars().collect();
+        let path = parts.nth(fields - 2).unwrap_or_default();
+        if codes.len() != 2 || path.is_empty() {
+            return Err(ChezmoiError::Parse(format!(
+                "unexpected git status record: {:?}",
+                record
+            )));
+        }
+        status.entries.push(GitEntry {
+            path: path.to_string(),
+            index: codes[0],
+            worktree: codes[1],
+        });
+        // A rename is followed by the original path
+        if fields == 9 {
+            records.next();
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::*;
+    use crate::chezmoi::ScriptedBackend;
+
+    fn entry(path: &str, index: char, worktree: char) -> GitEntry {
+        GitEntry {
+            path: path.to_string(),
+            index,
+            worktree,
+        }
+    }
+
+    #[test]
+    fn parse_status_reads_the_branch_headers() {
+        let output = "# branch.oid 1234567890abcdef1234567890abcdef12345678\0\
+                      # branch.head main\0\
+                      # branch.upstream origin/main\0\
+                      # branch.ab +2 -3\0";
+        let status = parse_status(output).unwrap();
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (2, 3));
+        assert!(status.entries.is_empty());
+    }
+
+    #[test]
+    fn parse_status_without_upstream_on_a_detached_head() {
+        let output = "# branch.oid 1234567890abcdef1234567890abcdef12345678\0\
+                      # branch.head (detached)\0";
+        assert_eq!(parse_status(output).unwrap(), GitStatus::default());
+    }
+
+    #[test]
+    fn parse_status_reads_every_kind_of_entry() {
+        let output = "# branch.head main\0\
+                      1 .M N... 100644 100644 100644 aaaaaaa aaaaaaa dot_bashrc\0\
+                      1 A. N... 000000 100644 100644 0000000 bbbbbbb dot config/new file\0\
+                      2 R. N... 100644 100644 100644 ccccccc ccccccc R100 dot_zshrc\0dot_zshenv\0\
+                      u UU N... 100644 100644 100644 100644 ddddddd eeeeeee fffffff dot_vimrc\0\
+                      ? private_dot_ssh/config\0\
+                      ! ignored\0";
+        let status = parse_status(output).unwrap();
+        assert_eq!(
+            status.entries,
+            [
+                entry("dot_bashrc", '.', 'M'),
+                entry("dot config/new file", 'A', '.'),
+                entry("dot_zshrc", 'R', '.'),
+                entry("dot_vimrc", 'U', 'U'),
+                entry("private_dot_ssh/config", '?', '?'),
+            ]
+        );
+        assert_eq!(status.staged_count(), 3);
+        assert!(status.entries[4].is_untracked());
+        assert!(!status.entries[1].has_unstaged_changes());
+    }
+
+    #[test]
+    fn parse_status_refuses_unknown_records() {
+        assert!(matches!(
+            parse_status("1 .M dot_bashrc\0"),
+            Err(ChezmoiError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_status("x something\0"),
+            Err(ChezmoiError::Parse(_))
+        ));
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "-c",
+                "user.name=Alice",
+                "-c",
+                "user.email=alice@example.com",
+            ])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn commit(clone: &Path, file: &str, message: &str) {
+        std::fs::write(clone.join(file), message).unwrap();
+        git(clone, &["add", file]);
+        git(clone, &["commit", "--quiet", "-m", message]);
+    }
+
+    #[test]
+    fn update_returns_the_pulled_commits() {
+        let dir =
+            std::env::temp_dir().join(format!("lazychezmoi-git-update-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (remote, source, other) = (
+            dir.join("remote.git"),
+            dir.join("source"),
+            dir.join("other"),
+        );
+        let path = |p: &Path| p.to_str().unwrap().to_string();
+
+        git(&dir, &["init", "--quiet", "--bare", &path(&remote)]);
+        git(&dir, &["clone", "--quiet", &path(&remote), &path(&other)]);
+        commit(&other, "dot_bashrc", "Add bashrc");
+        git(&other, &["push", "--quiet", "origin", "HEAD"]);
+        git(&dir, &["clone", "--quiet", &path(&remote), &path(&source)]);
+        commit(&other, "dot_zshrc", "Add zshrc");
+        commit(&other, "dot_bashrc", "Set aliases");
+        git(&other, &["push", "--quiet", "origin", "HEAD"]);
+
+        let backend = ScriptedBackend::new().with_git_dir(&source);
+        let commits = update(&backend).unwrap();
+        let subjects: Vec<&str> = commits.iter().map(|c| c.subject.as_str()).collect();
+        assert_eq!(subjects, ["Set aliases", "Add zshrc"]);
+        assert!(commits
+            .iter()
+            .all(|c| c.author == "Alice" && !c.hash.is_empty()));
+        assert_eq!(
+            std::fs::read_to_string(source.join("dot_bashrc")).unwrap(),
+            "Set aliases"
+        );
+
+        // Nothing new the second time
+        assert!(update(&backend).unwrap().is_empty());
+        let status = status(&backend).unwrap();
+        assert_eq!((status.ahead, status.behind), (0, 0));
+        assert!(status.upstream.is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
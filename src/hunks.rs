@@ -0,0 +1,613 @@
+use crate::chezmoi::{ChezmoiBackend, ChezmoiError, ChezmoiResult};
+
+/// One `@@` section of a unified diff.
+///
+/// In `chezmoi diff` output the old side (`-`) is the current target and the new side (`+`)
+/// is the target state computed from the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// Index of the `@@` line in the diff text.
+    pub start_line: usize,
+    /// Number of diff lines, header included.
+    pub line_count: usize,
+    old_start: usize,
+    new_start: usize,
+    /// Body lines with their ` `, `-` or `+` prefix, ending with `\n` unless the diff marks
+    /// them with "\ No newline at end of file".
+    lines: Vec<String>,
+}
+
+/// Splits a single-file unified diff into hunks. Binary diffs and file headers yield none.
+pub fn parse(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for (i, line) in diff.lines().enumerate() {
+        if let Some((old_start, new_start)) = parse_header(line) {
+            hunks.push(Hunk {
+                start_line: i,
+                line_count: 1,
+                old_start,
+                new_start,
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        // A new file section or the end of the hunk body
+        if !line.starts_with([' ', '-', '+', '\\']) || line.starts_with("diff --git ") {
+            continue;
+        }
+        if hunk.start_line + hunk.line_count != i {
+            continue;
+        }
+        hunk.line_count += 1;
+        // "\ No newline at end of file" applies to the line before it
+        if line.starts_with('\\') {
+            if let Some(last) = hunk.lines.last_mut() {
+                last.pop();
+            }
+        } else {
+            hunk.lines.push(format!("{}\n", line));
+        }
+    }
+    hunks
+}
+
+/// Start lines of both sides from a `@@ -a,b +c,d @@` header.
+pub fn parse_header(line: &str) -> Option<(usize, usize)> {
+    let ranges = line.strip_prefix("@@ ")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(' ')?;
+    let start = |range: &str| range.split(',').next()?.parse::<usize>().ok();
+    Some((
+        start(old.strip_prefix('-')?)?,
+        start(new.strip_prefix('+')?)?,
+    ))
+}
+
+/// Direction in which hunks are applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Turns the old side (the target) into the new side (the target state).
+    Forward,
+    /// Turns the new side back into the old side.
+    Reverse,
+}
+
+/// A line without its `\n` or `\r\n` terminator.
+fn line_text(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => line,
+    }
+}
+
+/// Applies `hunks` to `content`, checking that the lines they replace are really there.
+///
+/// Lines outside the hunks, context lines included, are kept byte for byte; new lines get the
+/// line ending of the file.
+pub fn patch(content: &str, hunks: &[&Hunk], direction: Direction) -> Result<String, String> {
+    let (removed, added) = match direction {
+        Direction::Forward => ('-', '+'),
+        Direction::Reverse => ('+', '-'),
+    };
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let eol = match lines.first() {
+        Some(line) if line.ends_with("\r\n") => "\r\n",
+        _ => "\n",
+    };
+    let mut hunks = hunks.to_vec();
+    hunks.sort_by_key(|hunk| hunk.start_line);
+
+    let mut output = String::with_capacity(content.len());
+    let mut next = 0;
+    for hunk in hunks {
+        let expected: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|l| !l.starts_with(added))
+            .map(|l| line_text(&l[1..]))
+            .collect();
+        let start = match direction {
+            Direction::Forward => hunk.old_start,
+            Direction::Reverse => hunk.new_start,
+        };
+        // An empty side starts after the given line rather than on it
+        let start = if expected.is_empty() {
+            start
+        } else {
+            start.saturating_sub(1)
+        };
+        if start < next
+            || start + expected.len() > lines.len()
+            || lines[start..start + expected.len()]
+                .iter()
+                .map(|line| line_text(line))
+                .ne(expected.iter().copied())
+        {
+            return Err(format!(
+                "hunk at line {} does not match the file anymore",
+                start + 1
+            ));
+        }
+        output.extend(lines[next..start].iter().copied());
+        let mut current = start;
+        for line in &hunk.lines {
+            if line.starts_with(added) {
+                output.push_str(line_text(&line[1..]));
+                if line.ends_with('\n') {
+                    output.push_str(eol);
+                }
+                continue;
+            }
+            if !line.starts_with(removed) {
+                output.push_str(lines[current]);
+            }
+            current += 1;
+        }
+        next = current;
+    }
+    output.extend(lines[next..].iter().copied());
+    Ok(output)
+}
+
+/// Source attributes whose file contents are not the target contents, so they can't be
+/// patched with hunks of `chezmoi diff`.
+const UNPATCHABLE_ATTRIBUTES: [&str; 4] = ["encrypted_", "symlink_", "modify_", "run_"];
+
+/// Every prefix chezmoi may put in front of a source file name.
+const SOURCE_PREFIXES: [&str; 16] = [
+    "after_",
+    "before_",
+    "create_",
+    "empty_",
+    "encrypted_",
+    "exact_",
+    "executable_",
+    "literal_",
+    "modify_",
+    "once_",
+    "onchange_",
+    "private_",
+    "readonly_",
+    "remove_",
+    "run_",
+    "symlink_",
+];
+
+/// Attribute prefixes of a source file name, e.g. `["private_", "encrypted_"]`.
+pub fn source_attributes(file_name: &str) -> Vec<&'static str> {
+    let mut attributes = Vec::new();
+    let mut rest = file_name;
+    while let Some(prefix) = SOURCE_PREFIXES.iter().find(|p| rest.starts_with(*p)) {
+        attributes.push(*prefix);
+        rest = &rest[prefix.len()..];
+        // Anything after literal_ is part of the name
+        if *prefix == "literal_" {
+            break;
+        }
+    }
+    attributes
+}
+
+/// Why the hunks of `path` can't be written to its source file at `source_path`, if so.
+pub fn unpatchable_reason(path: &str, source_path: &str) -> Option<String> {
+    let file_name = source_path.rsplit('/').next().unwrap_or_default();
+    if file_name.ends_with(".tmpl") {
+        return Some(format!(
+            "{} is a template: its hunks can't be patched, edit the template instead",
+            path
+        ));
+    }
+    source_attributes(file_name)
+        .into_iter()
+        .find(|attribute| UNPATCHABLE_ATTRIBUTES.contains(attribute))
+        .map(|attribute| {
+            format!(
+                "{} is stored with the {} attribute: its hunks can't be patched, use the whole file instead",
+                path,
+                attribute.trim_end_matches('_')
+            )
+        })
+}
+
+/// File that hunks are written to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchSide {
+    /// Re-add: the local version of the hunks goes into the source file.
+    Source,
+    /// Apply, or discard local changes: the target state version goes into the target.
+    Target,
+}
+
+/// Writes only the chosen hunks of `diff` to the source file or the target of `path`.
+///
+/// The source file can only be patched when its contents are the target contents, i.e. for
+/// plain files. The target holds rendered contents, so templates and encrypted files can be
+/// patched there; only symlinks and scripts, which have no target file, are refused.
+pub fn patch_file(
+    backend: &dyn ChezmoiBackend,
+    path: &str,
+    diff: &str,
+    selected: &[usize],
+    side: PatchSide,
+) -> ChezmoiResult<()> {
+    let source_path = backend.source_path(path)?;
+    let (file, direction) = match side {
+        PatchSide::Source => {
+            if let Some(reason) = unpatchable_reason(path, &source_path) {
+                return Err(ChezmoiError::Unsupported(reason));
+            }
+            (source_path, Direction::Reverse)
+        }
+        PatchSide::Target => {
+            let file_name = source_path.rsplit('/').next().unwrap_or_default();
+            if let Some(attribute) = source_attributes(file_name)
+                .into_iter()
+                .find(|attribute| ["symlink_", "run_"].contains(attribute))
+            {
+                return Err(ChezmoiError::Unsupported(format!(
+                    "{} is a {}: it has no target file to patch",
+                    path,
+                    if attribute == "run_" {
+                        "script"
+                    } else {
+                        "symlink"
+                    }
+                )));
+            }
+            (backend.target_path(path), Direction::Forward)
+        }
+    };
+
+    let hunks = parse(diff);
+    let chosen: Vec<&Hunk> = selected.iter().filter_map(|&i| hunks.get(i)).collect();
+    if chosen.is_empty() {
+        return Ok(());
+    }
+    let contents = backend.read_file(&file)?;
+    let patched = patch(&contents, &chosen, direction)
+        .map_err(|e| ChezmoiError::Unsupported(format!("{}: {}", path, e)))?;
+    backend.write_file(&file, &patched)
+}
+
+/// The text of the chosen hunks, e.g. to show what is about to change.
+pub fn hunks_text(diff: &str, selected: &[usize]) -> String {
+    let lines: Vec<&str> = diff.lines().collect();
+    let hunks = parse(diff);
+    let mut text = String::new();
+    for hunk in selected.iter().filter_map(|&i| hunks.get(i)) {
+        for line in &lines[hunk.start_line..hunk.start_line + hunk.line_count] {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chezmoi::ScriptedBackend;
+
+    const TARGET: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\n";
+    const TARGET_STATE: &str = "one\nTWO\nthree\nfour\nsix\nseven\neight\nnine\nten\n";
+
+    /// `chezmoi diff` of [`TARGET`] against [`TARGET_STATE`]: a change, a removal and an
+    /// addition.
+    const DIFF: &str = "diff --git a/.bashrc b/.bashrc
+index 1111111..2222222 100644
+--- a/.bashrc
++++ b/.bashrc
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+@@ -4,3 +4,2 @@
+ four
+-five
+ six
+@@ -8,2 +7,3 @@
+ eight
+ nine
++ten
+";
+
+    fn patch_with(
+        content: &str,
+        diff: &str,
+        selected: &[usize],
+        direction: Direction,
+    ) -> Result<String, String> {
+        let hunks = parse(diff);
+        let chosen: Vec<&Hunk> = selected.iter().map(|&i| &hunks[i]).collect();
+        patch(content, &chosen, direction)
+    }
+
+    #[test]
+    fn parse_splits_hunks_after_the_file_header() {
+        let hunks = parse(DIFF);
+        let positions: Vec<(usize, usize)> = hunks
+            .iter()
+            .map(|hunk| (hunk.start_line, hunk.line_count))
+            .collect();
+        assert_eq!(positions, [(4, 5), (9, 4), (13, 4)]);
+        assert_eq!(hunks[1].lines, [" four\n", "-five\n", " six\n"]);
+    }
+
+    #[test]
+    fn parse_ignores_binary_diffs() {
+        let diff = "diff --git a/image.png b/image.png\nBinary files differ\n";
+        assert!(parse(diff).is_empty());
+    }
+
+    #[test]
+    fn patch_applies_every_hunk() {
+        assert_eq!(
+            patch_with(TARGET, DIFF, &[0, 1, 2], Direction::Forward).unwrap(),
+            TARGET_STATE
+        );
+        assert_eq!(
+            patch_with(TARGET_STATE, DIFF, &[0, 1, 2], Direction::Reverse).unwrap(),
+            TARGET
+        );
+    }
+
+    #[test]
+    fn patch_applies_only_the_chosen_hunks() {
+        assert_eq!(
+            patch_with(TARGET, DIFF, &[2, 0], Direction::Forward).unwrap(),
+            "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n"
+        );
+        assert_eq!(
+            patch_with(TARGET_STATE, DIFF, &[1], Direction::Reverse).unwrap(),
+            "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n"
+        );
+    }
+
+    #[test]
+    fn patch_add_only_hunk() {
+        assert_eq!(
+            patch_with(TARGET, DIFF, &[2], Direction::Forward).unwrap(),
+            format!("{}ten\n", TARGET)
+        );
+        // Without context, the added lines go after the old start line
+        let diff = "@@ -1,0 +2 @@\n+inserted\n";
+        assert_eq!(
+            patch_with("first\nlast\n", diff, &[0], Direction::Forward).unwrap(),
+            "first\ninserted\nlast\n"
+        );
+        assert_eq!(
+            patch_with("first\ninserted\nlast\n", diff, &[0], Direction::Reverse).unwrap(),
+            "first\nlast\n"
+        );
+    }
+
+    #[test]
+    fn patch_remove_only_hunk() {
+        assert_eq!(
+            patch_with(TARGET, DIFF, &[1], Direction::Forward).unwrap(),
+            "one\ntwo\nthree\nfour\nsix\nseven\neight\nnine\n"
+        );
+        let diff = "@@ -2 +1,0 @@\n-removed\n";
+        assert_eq!(
+            patch_with("first\nremoved\nlast\n", diff, &[0], Direction::Forward).unwrap(),
+            "first\nlast\n"
+        );
+        assert_eq!(
+            patch_with("first\nlast\n", diff, &[0], Direction::Reverse).unwrap(),
+            "first\nremoved\nlast\n"
+        );
+    }
+
+    #[test]
+    fn patch_keeps_a_missing_trailing_newline() {
+        let diff = "@@ -1,2 +1,2 @@
+ a
+-b
+\\ No newline at end of file
++c
+\\ No newline at end of file
+";
+        assert_eq!(parse(diff)[0].lines, [" a\n", "-b", "+c"]);
+        assert_eq!(
+            patch_with("a\nb", diff, &[0], Direction::Forward).unwrap(),
+            "a\nc"
+        );
+        assert_eq!(
+            patch_with("a\nc", diff, &[0], Direction::Reverse).unwrap(),
+            "a\nb"
+        );
+    }
+
+    #[test]
+    fn patch_adds_and_removes_a_final_newline() {
+        let diff = "@@ -1,2 +1,3 @@
+ a
+-b
+\\ No newline at end of file
++b
++c
+";
+        assert_eq!(
+            patch_with("a\nb", diff, &[0], Direction::Forward).unwrap(),
+            "a\nb\nc\n"
+        );
+        assert_eq!(
+            patch_with("a\nb\nc\n", diff, &[0], Direction::Reverse).unwrap(),
+            "a\nb"
+        );
+    }
+
+    #[test]
+    fn patch_keeps_crlf_line_endings() {
+        let crlf = |text: &str| text.replace('\n', "\r\n");
+        // chezmoi diff keeps the carriage returns of the file
+        let diff = crlf(DIFF);
+        assert_eq!(
+            patch_with(&crlf(TARGET_STATE), &diff, &[0], Direction::Reverse).unwrap(),
+            crlf("one\ntwo\nthree\nfour\nsix\nseven\neight\nnine\nten\n")
+        );
+        assert_eq!(
+            patch_with(&crlf(TARGET), &diff, &[2], Direction::Forward).unwrap(),
+            crlf(&format!("{}ten\n", TARGET))
+        );
+    }
+
+    #[test]
+    fn patch_leaves_untouched_lines_alone() {
+        let target = "one\r\ntwo\nthree\nfour\nfive\nsix\nseven\neight\t\nnine\n";
+        let diff = DIFF.replace(" eight\n", " eight\t\n");
+        assert_eq!(
+            patch_with(target, &diff, &[1], Direction::Forward).unwrap(),
+            "one\r\ntwo\nthree\nfour\nsix\nseven\neight\t\nnine\n"
+        );
+    }
+
+    #[test]
+    fn patch_refuses_a_changed_file() {
+        let changed = TARGET.replace("five", "FIVE");
+        assert_eq!(
+            patch_with(&changed, DIFF, &[0, 1], Direction::Forward),
+            Err("hunk at line 4 does not match the file anymore".to_string())
+        );
+        // Hunks past the end of the file don't match either
+        assert_eq!(
+            patch_with("one\ntwo\n", DIFF, &[2], Direction::Forward),
+            Err("hunk at line 8 does not match the file anymore".to_string())
+        );
+    }
+
+    #[test]
+    fn patch_file_re_adds_into_the_source_file() {
+        let backend = ScriptedBackend::new()
+            .with_source_path(".bashrc", "/source/dot_bashrc")
+            .with_file("/source/dot_bashrc", TARGET_STATE);
+        patch_file(&backend, ".bashrc", DIFF, &[0], PatchSide::Source).unwrap();
+        assert_eq!(
+            backend.file("/source/dot_bashrc").unwrap(),
+            "one\ntwo\nthree\nfour\nsix\nseven\neight\nnine\nten\n"
+        );
+        assert_eq!(backend.calls(), ["write /source/dot_bashrc"]);
+    }
+
+    #[test]
+    fn patch_file_applies_to_the_target() {
+        let backend = ScriptedBackend::new()
+            .with_source_path(".bashrc", "/source/encrypted_dot_bashrc.tmpl")
+            .with_file("/home/.bashrc", TARGET);
+        patch_file(&backend, ".bashrc", DIFF, &[1, 2], PatchSide::Target).unwrap();
+        assert_eq!(
+            backend.file("/home/.bashrc").unwrap(),
+            "one\ntwo\nthree\nfour\nsix\nseven\neight\nnine\nten\n"
+        );
+    }
+
+    #[test]
+    fn patch_file_keeps_the_line_endings_of_the_source_file() {
+        let crlf = |text: &str| text.replace('\n', "\r\n");
+        let backend = ScriptedBackend::new()
+            .with_source_path(".bashrc", "/source/dot_bashrc")
+            .with_file("/source/dot_bashrc", &crlf(TARGET_STATE));
+        patch_file(&backend, ".bashrc", &crlf(DIFF), &[1], PatchSide::Source).unwrap();
+        assert_eq!(
+            backend.file("/source/dot_bashrc").unwrap(),
+            crlf("one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n")
+        );
+    }
+
+    #[test]
+    fn patch_file_keeps_the_line_endings_of_the_target() {
+        let crlf = |text: &str| text.replace('\n', "\r\n");
+        let backend = ScriptedBackend::new().with_file("/home/.bashrc", &crlf(TARGET));
+        patch_file(&backend, ".bashrc", &crlf(DIFF), &[0, 2], PatchSide::Target).unwrap();
+        assert_eq!(
+            backend.file("/home/.bashrc").unwrap(),
+            crlf("one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n")
+        );
+    }
+
+    #[test]
+    fn patch_file_leaves_a_changed_file_alone() {
+        let backend = ScriptedBackend::new().with_file("/home/.bashrc", "something else\n");
+        let error = patch_file(&backend, ".bashrc", DIFF, &[0], PatchSide::Target).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            ".bashrc: hunk at line 1 does not match the file anymore"
+        );
+        assert_eq!(backend.file("/home/.bashrc").unwrap(), "something else\n");
+        assert!(backend.calls().is_empty());
+    }
+
+    #[test]
+    fn patch_file_refuses_sources_that_are_not_the_target_contents() {
+        for source_path in [
+            "/source/dot_bashrc.tmpl",
+            "/source/private_encrypted_dot_bashrc.age",
+            "/source/modify_dot_bashrc",
+            "/source/symlink_dot_bashrc",
+            "/source/run_once_install.sh",
+        ] {
+            let backend = ScriptedBackend::new()
+                .with_source_path(".bashrc", source_path)
+                .with_file(source_path, TARGET_STATE);
+            let result = patch_file(&backend, ".bashrc", DIFF, &[0], PatchSide::Source);
+            assert!(
+                matches!(result, Err(ChezmoiError::Unsupported(_))),
+                "{}",
+                source_path
+            );
+            assert_eq!(backend.file(source_path).unwrap(), TARGET_STATE);
+        }
+    }
+
+    #[test]
+    fn patch_file_refuses_targets_without_a_file() {
+        for source_path in ["/source/symlink_dot_bashrc", "/source/run_once_install.sh"] {
+            let backend = ScriptedBackend::new()
+                .with_source_path(".bashrc", source_path)
+                .with_file("/home/.bashrc", TARGET);
+            let result = patch_file(&backend, ".bashrc", DIFF, &[0], PatchSide::Target);
+            assert!(
+                matches!(result, Err(ChezmoiError::Unsupported(_))),
+                "{}",
+                source_path
+            );
+        }
+    }
+
+    #[test]
+    fn unpatchable_reason_names_the_attribute() {
+        let reason = |source_path| unpatchable_reason(".bashrc", source_path);
+        assert_eq!(
+            reason("/source/dot_bashrc.tmpl").unwrap(),
+            ".bashrc is a template: its hunks can't be patched, edit the template instead"
+        );
+        for (source_path, attribute) in [
+            ("/source/private_encrypted_dot_bashrc.age", "encrypted"),
+            ("/source/modify_dot_bashrc", "modify"),
+            ("/source/symlink_dot_bashrc", "symlink"),
+            ("/source/run_onchange_before_install.sh", "run"),
+        ] {
+            assert_eq!(
+                reason(source_path).unwrap(),
+                format!(
+                    ".bashrc is stored with the {} attribute: its hunks can't be patched, use the whole file instead",
+                    attribute
+                )
+            );
+        }
+        assert_eq!(reason("/source/private_executable_dot_bashrc"), None);
+        // Attribute names after literal_ are part of the file name
+        assert_eq!(reason("/source/literal_run_me"), None);
+    }
+
+    #[test]
+    fn hunks_text_keeps_the_chosen_hunks() {
+        assert_eq!(
+            hunks_text(DIFF, &[1]),
+            "@@ -4,3 +4,2 @@\n four\n-five\n six\n"
+        );
+    }
+}
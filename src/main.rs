@@ -1,14 +1,28 @@
 pub use app::App;
 
+pub mod ansi;
 pub mod app;
 pub mod chezmoi;
+pub mod config;
+pub mod diff_cache;
+pub mod discard;
+pub mod filters;
+pub mod fuzzy;
+pub mod git;
+pub mod hunks;
+pub mod plan;
+pub mod split_diff;
+pub mod syntax;
+pub mod tree;
 pub mod utils;
+pub mod worker;
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
     chezmoi::check_installed()?;
+    let config = config::load()?;
     let terminal = ratatui::init();
-    let result = App::new().run(terminal);
+    let result = App::new().with_config(config).run(terminal);
     ratatui::restore();
     result
 }
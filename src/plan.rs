@@ -0,0 +1,98 @@
+use crate::chezmoi::{self, ChezmoiBackend, ChezmoiResult};
+
+/// What applying does to one target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanAction {
+    Write,
+    Create,
+    /// Removed because of `.chezmoiremove` or an exact directory.
+    Delete,
+    RunScript,
+}
+
+impl PlanAction {
+    pub const ALL: [PlanAction; 4] = [
+        PlanAction::Write,
+        PlanAction::Create,
+        PlanAction::Delete,
+        PlanAction::RunScript,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            PlanAction::Write => "Targets overwritten",
+            PlanAction::Create => "Targets created",
+            PlanAction::Delete => "Targets deleted",
+            PlanAction::RunScript => "Scripts run",
+        }
+    }
+}
+
+/// Everything an execute will touch, shown for confirmation before it runs.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionPlan {
+    /// Files to re-add, with the source file each one overwrites.
+    pub re_add: Vec<(String, String)>,
+    /// Files to apply, as passed to chezmoi.
+    pub apply: Vec<String>,
+    /// Targets changed by applying `apply`, which may include files below them.
+    pub targets: Vec<(String, PlanAction)>,
+}
+
+impl ExecutionPlan {
+    pub fn targets(&self, action: PlanAction) -> impl Iterator<Item = &str> {
+        self.targets
+            .iter()
+            .filter(move |(_, a)| *a == action)
+            .map(|(path, _)| path.as_str())
+    }
+}
+
+/// Dry-runs the apply batch and looks up the source files of the re-add batch.
+///
+/// `scripts` are the apply paths that `chezmoi status` reports as scripts to run: a dry run
+/// does not tell them apart from regular files.
+pub fn build(
+    backend: &dyn ChezmoiBackend,
+    re_add: &[String],
+    apply: &[String],
+    scripts: &[String],
+) -> ChezmoiResult<ExecutionPlan> {
+    let re_add = re_add
+        .iter()
+        .map(|path| Ok((path.clone(), backend.source_path(path)?)))
+        .collect::<ChezmoiResult<Vec<_>>>()?;
+
+    let mut targets = Vec::new();
+    if !apply.is_empty() {
+        let mut sections: Vec<(String, String)> =
+            chezmoi::split_diff(&backend.dry_run_apply(apply)?)
+                .into_iter()
+                .collect();
+        sections.sort();
+        for (path, diff) in sections {
+            let action = if scripts.contains(&path) {
+                PlanAction::RunScript
+            } else if diff.lines().any(|l| l.starts_with("deleted file mode")) {
+                PlanAction::Delete
+            } else if diff.lines().any(|l| l.starts_with("new file mode")) {
+                PlanAction::Create
+            } else {
+                PlanAction::Write
+            };
+            targets.push((path, action));
+        }
+        // Scripts don't always show up in the dry run output
+        for script in scripts {
+            if !targets.iter().any(|(path, _)| path == script) {
+                targets.push((script.clone(), PlanAction::RunScript));
+            }
+        }
+    }
+
+    Ok(ExecutionPlan {
+        re_add,
+        apply: apply.to_vec(),
+        targets,
+    })
+}
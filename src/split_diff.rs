@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+use crate::hunks;
+
+/// Kind of one side of a row of the side-by-side diff.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Context,
+    Removed,
+    Added,
+}
+
+/// One line of the old (target) or new (target state) side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SideLine {
+    /// Line number in its file, starting at 1.
+    pub number: usize,
+    pub kind: LineKind,
+    /// The line without its diff prefix.
+    pub text: String,
+    /// Byte ranges of `text` that differ from the line it is paired with.
+    pub changed: Vec<Range<usize>>,
+}
+
+/// A row of the side-by-side diff.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitRow {
+    /// File headers and `@@` lines, shown across both sides.
+    Header { diff_line: usize, text: String },
+    /// A context line, or a removed line paired with an added one. A side is missing when
+    /// one side of a change has more lines than the other.
+    Pair {
+        diff_line: usize,
+        old: Option<SideLine>,
+        new: Option<SideLine>,
+    },
+}
+
+impl SplitRow {
+    /// Index in the unified diff of the first line shown on this row.
+    pub fn diff_line(&self) -> usize {
+        match self {
+            SplitRow::Header { diff_line, .. } | SplitRow::Pair { diff_line, .. } => *diff_line,
+        }
+    }
+
+    /// Text of the row as searched, both sides for pairs.
+    pub fn text(&self) -> String {
+        match self {
+            SplitRow::Header { text, .. } => text.clone(),
+            SplitRow::Pair { old, new, .. } => {
+                let side = |line: &Option<SideLine>| {
+                    line.as_ref().map(|l| l.text.clone()).unwrap_or_default()
+                };
+                format!("{}\t{}", side(old), side(new))
+            }
+        }
+    }
+}
+
+/// Lays out a unified diff side by side, pairing removed lines with the added lines that
+/// follow them and marking the words that changed between paired lines.
+pub fn rows(diff: &str) -> Vec<SplitRow> {
+    let mut rows = Vec::new();
+    // Removed and added lines of the current change, paired once the change ends
+    let mut removed: Vec<(usize, SideLine)> = Vec::new();
+    let mut added: Vec<(usize, SideLine)> = Vec::new();
+    let mut numbers: Option<(usize, usize)> = None;
+
+    for (i, line) in diff.lines().enumerate() {
+        let in_hunk = numbers.is_some() && line.starts_with([' ', '-', '+', '\\']);
+        if !in_hunk || line.starts_with(' ') {
+            flush_change(&mut rows, &mut removed, &mut added);
+        }
+        if let Some(header) = hunks::parse_header(line) {
+            numbers = Some(header);
+            rows.push(SplitRow::Header {
+                diff_line: i,
+                text: line.to_string(),
+            });
+            continue;
+        }
+        let Some((old_number, new_number)) = numbers.as_mut().filter(|_| in_hunk) else {
+            numbers = None;
+            rows.push(SplitRow::Header {
+                diff_line: i,
+                text: line.to_string(),
+            });
+            continue;
+        };
+        let side_line = |number: usize, kind: LineKind| SideLine {
+            number,
+            kind,
+            text: line[1..].to_string(),
+            changed: Vec::new(),
+        };
+        match &line[..1] {
+            "-" => {
+                removed.push((i, side_line(*old_number, LineKind::Removed)));
+                *old_number += 1;
+            }
+            "+" => {
+                added.push((i, side_line(*new_number, LineKind::Added)));
+                *new_number += 1;
+            }
+            " " => {
+                rows.push(SplitRow::Pair {
+                    diff_line: i,
+                    old: Some(side_line(*old_number, LineKind::Context)),
+                    new: Some(side_line(*new_number, LineKind::Context)),
+                });
+                *old_number += 1;
+                *new_number += 1;
+            }
+            // "\ No newline at end of file"
+            _ => {}
+        }
+    }
+    flush_change(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+fn flush_change(
+    rows: &mut Vec<SplitRow>,
+    removed: &mut Vec<(usize, SideLine)>,
+    added: &mut Vec<(usize, SideLine)>,
+) {
+    let count = removed.len().max(added.len());
+    let mut removed = removed.drain(..);
+    let mut added = added.drain(..);
+    for _ in 0..count {
+        let (old_line, mut old) = removed.next().unzip();
+        let (new_line, mut new) = added.next().unzip();
+        if let (Some(old), Some(new)) = (&mut old, &mut new) {
+            (old.changed, new.changed) = changed_words(&old.text, &new.text);
+        }
+        rows.push(SplitRow::Pair {
+            diff_line: old_line.or(new_line).unwrap_or_default(),
+            old,
+            new,
+        });
+    }
+}
+
+/// Longest token sequences compared word by word; longer lines are marked as a whole.
+const MAX_WORD_DIFF_TOKENS: usize = 500;
+
+/// Byte ranges of the tokens of `old` and of `new` that are not common to both.
+fn changed_words(old: &str, new: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    if old_tokens.len() > MAX_WORD_DIFF_TOKENS || new_tokens.len() > MAX_WORD_DIFF_TOKENS {
+        let whole = |text: &str| std::iter::once(0..text.len()).collect();
+        return (whole(old), whole(new));
+    }
+    let token = |text: &str, range: &Range<usize>| text[range.clone()].to_string();
+
+    // Longest common subsequence of the tokens, filled from the end
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if token(old, &old_tokens[i]) == token(new, &new_tokens[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut old_changed, mut new_changed) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && token(old, &old_tokens[i]) == token(new, &new_tokens[j]) {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            push_range(&mut old_changed, old_tokens[i].clone());
+            i += 1;
+        } else {
+            push_range(&mut new_changed, new_tokens[j].clone());
+            j += 1;
+        }
+    }
+    (old_changed, new_changed)
+}
+
+/// Appends `range`, merging it with the last one when they touch.
+fn push_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+/// Splits a line into words, runs of whitespace and single punctuation characters.
+fn tokenize(text: &str) -> Vec<Range<usize>> {
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            0
+        } else if c.is_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+    let mut tokens: Vec<Range<usize>> = Vec::new();
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        let current = class(c);
+        match tokens.last_mut() {
+            Some(last) if previous == Some(current) && current != 2 => last.end = i + c.len_utf8(),
+            _ => tokens.push(i..i + c.len_utf8()),
+        }
+        previous = Some(current);
+    }
+    tokens
+}
@@ -0,0 +1,293 @@
+use std::ops::Range;
+
+use ratatui::style::{Color, Modifier, Style};
+
+/// Languages highlighted in the diff pane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Shell,
+    Toml,
+    Yaml,
+    Lua,
+    Json,
+}
+
+/// How to highlight a file: its language, if known, and whether it is a Go template.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Syntax {
+    pub language: Option<Language>,
+    pub template: bool,
+}
+
+/// Shell startup files, which have no extension.
+const SHELL_FILE_NAMES: [&str; 14] = [
+    ".bashrc",
+    ".bash_profile",
+    ".bash_login",
+    ".bash_logout",
+    ".bash_aliases",
+    ".profile",
+    ".zshrc",
+    ".zshenv",
+    ".zprofile",
+    ".zlogin",
+    ".zlogout",
+    ".kshrc",
+    ".envrc",
+    ".xprofile",
+];
+
+const SHELL_KEYWORDS: [&str; 24] = [
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "return", "export", "local", "readonly", "alias", "source", "unset", "set",
+    "eval", "exec",
+];
+
+const LUA_KEYWORDS: [&str; 19] = [
+    "and", "break", "do", "else", "elseif", "end", "for", "function", "goto", "if", "in", "local",
+    "not", "or", "repeat", "return", "then", "until", "while",
+];
+
+const CONSTANTS: [&str; 8] = ["true", "false", "null", "nil", "yes", "no", "on", "off"];
+
+impl Syntax {
+    /// Works out the syntax of `path` from its extension or file name, or else from the
+    /// shebang on `first_line`. `None` when there is nothing to highlight.
+    ///
+    /// Targets are always rendered, so only source paths (`dot_gitconfig.tmpl`) are detected
+    /// as Go templates, highlighted on top of the language of the rest of the name.
+    pub fn detect(path: &str, first_line: Option<&str>) -> Option<Syntax> {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        let (file_name, template) = match file_name.strip_suffix(".tmpl") {
+            Some(name) => (name, true),
+            None => (file_name, false),
+        };
+        let extension = file_name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .filter(|_| !SHELL_FILE_NAMES.contains(&file_name));
+        let language = match extension {
+            Some("toml") => Some(Language::Toml),
+            Some("yaml" | "yml") => Some(Language::Yaml),
+            Some("lua") => Some(Language::Lua),
+            Some("json" | "jsonc") => Some(Language::Json),
+            Some("sh" | "bash" | "zsh" | "ksh") => Some(Language::Shell),
+            _ if SHELL_FILE_NAMES.contains(&file_name) => Some(Language::Shell),
+            _ => first_line
+                .and_then(|line| line.strip_prefix("#!"))
+                .and_then(|shebang| {
+                    let interpreter = shebang.split_whitespace().collect::<Vec<_>>();
+                    // `#!/usr/bin/env bash` names the interpreter second
+                    let name = match interpreter.as_slice() {
+                        [env, name, ..] if env.ends_with("/env") => *name,
+                        [path, ..] => path.rsplit('/').next().unwrap_or_default(),
+                        [] => "",
+                    };
+                    match name {
+                        "sh" | "bash" | "zsh" | "ksh" | "dash" => Some(Language::Shell),
+                        "lua" | "luajit" => Some(Language::Lua),
+                        _ => None,
+                    }
+                }),
+        };
+        (language.is_some() || template).then_some(Syntax { language, template })
+    }
+
+    /// Foreground styles of the tokens of one line, as byte ranges of `line`. Each line is
+    /// highlighted on its own, so constructs spanning lines (e.g. block comments) are not
+    /// recognised.
+    pub fn highlight(&self, line: &str) -> Vec<(Range<usize>, Style)> {
+        let mut styles = match self.language {
+            Some(language) => highlight_language(language, line),
+            None => Vec::new(),
+        };
+        if self.template {
+            styles.extend(template_actions(line));
+        }
+        styles
+    }
+}
+
+fn comment_style() -> Style {
+    Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::ITALIC)
+}
+
+fn highlight_language(language: Language, line: &str) -> Vec<(Range<usize>, Style)> {
+    let string_style = Style::default().fg(Color::Yellow);
+    let keyword_style = Style::default().fg(Color::Magenta);
+    let constant_style = Style::default().fg(Color::LightCyan);
+    let key_style = Style::default().fg(Color::Blue);
+    let variable_style = Style::default().fg(Color::Cyan);
+
+    let (comment, keywords): (&str, &[&str]) = match language {
+        Language::Shell => ("#", &SHELL_KEYWORDS),
+        Language::Lua => ("--", &LUA_KEYWORDS),
+        Language::Toml | Language::Yaml => ("#", &[]),
+        Language::Json => ("//", &[]),
+    };
+
+    let mut styles = Vec::new();
+    // TOML tables are highlighted as a whole
+    if language == Language::Toml && line.trim_start().starts_with('[') {
+        let end = line.find('#').unwrap_or(line.len());
+        styles.push((0..end, key_style));
+        if end < line.len() {
+            styles.push((end..line.len(), comment_style()));
+        }
+        return styles;
+    }
+    if let Some(key) = key_range(language, line) {
+        styles.push((key, key_style));
+    }
+
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        let c = rest.chars().next().unwrap_or_default();
+        let at_word_start = i == 0 || !is_word_byte(bytes[i - 1]);
+        if rest.starts_with(comment)
+            && (language != Language::Shell || i == 0 || bytes[i - 1].is_ascii_whitespace())
+        {
+            styles.push((i..line.len(), comment_style()));
+            break;
+        }
+        if c == '"' || c == '\'' || (c == '`' && language == Language::Shell) {
+            let end = string_end(line, i, c);
+            // JSON keys are strings too, already styled
+            if !styles.iter().any(|(range, _)| range.start == i) {
+                styles.push((i..end, string_style));
+            }
+            i = end;
+            continue;
+        }
+        if c == '$' && language == Language::Shell {
+            let end = variable_end(line, i);
+            styles.push((i..end, variable_style));
+            i = end;
+            continue;
+        }
+        if (c == '&' || c == '*') && language == Language::Yaml && at_word_start {
+            let end = word_end(line, i + 1);
+            if end > i + 1 {
+                styles.push((i..end, variable_style));
+                i = end;
+                continue;
+            }
+        }
+        if is_word_byte(c as u8) && c.is_ascii() && at_word_start {
+            let end = word_end(line, i);
+            let word = &line[i..end];
+            if keywords.contains(&word) {
+                styles.push((i..end, keyword_style));
+            } else if word.starts_with(|c: char| c.is_ascii_digit())
+                || (language != Language::Shell && CONSTANTS.contains(&word))
+            {
+                styles.push((i..end, constant_style));
+            }
+            i = end;
+            continue;
+        }
+        i += c.len_utf8();
+    }
+    styles
+}
+
+/// The key of a `key = value` (TOML), `key: value` (YAML) or `"key": value` (JSON) line.
+fn key_range(language: Language, line: &str) -> Option<Range<usize>> {
+    let start = line.len() - line.trim_start().len();
+    match language {
+        Language::Toml => {
+            let end = line.find('=')?;
+            let key = line[start..end].trim_end();
+            (!key.is_empty() && !key.starts_with('#')).then(|| start..start + key.len())
+        }
+        Language::Yaml => {
+            let start = line[start..]
+                .strip_prefix("- ")
+                .map_or(start, |rest| line.len() - rest.trim_start().len());
+            if line[start..].starts_with('#') {
+                return None;
+            }
+            let end = line[start..]
+                .find(": ")
+                .or_else(|| line[start..].ends_with(':').then(|| line.len() - start - 1))?;
+            let key = &line[start..start + end];
+            ((!key.is_empty() && !key.contains(['"', '\''])) || key.starts_with(['"', '\'']))
+                .then(|| start..start + end)
+        }
+        Language::Json => {
+            if !line[start..].starts_with('"') {
+                return None;
+            }
+            let end = string_end(line, start, '"');
+            line[end..]
+                .trim_start()
+                .starts_with(':')
+                .then_some(start..end)
+        }
+        Language::Shell | Language::Lua => None,
+    }
+}
+
+/// Go template actions, `{{ ... }}`.
+fn template_actions(line: &str) -> Vec<(Range<usize>, Style)> {
+    let style = Style::default()
+        .fg(Color::LightMagenta)
+        .add_modifier(Modifier::BOLD);
+    let mut actions = Vec::new();
+    let mut from = 0;
+    while let Some(start) = line[from..].find("{{").map(|i| from + i) {
+        let end = line[start..]
+            .find("}}")
+            .map_or(line.len(), |i| start + i + 2);
+        actions.push((start..end, style));
+        from = end;
+    }
+    actions
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.' || byte == b'-'
+}
+
+fn word_end(line: &str, start: usize) -> usize {
+    line[start..]
+        .find(|c: char| !c.is_ascii() || !is_word_byte(c as u8))
+        .map_or(line.len(), |i| start + i)
+}
+
+/// End of the string starting with `quote` at `start`, escapes included.
+fn string_end(line: &str, start: usize, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in line[start + 1..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != '\'' {
+            escaped = true;
+        } else if c == quote {
+            return start + 1 + i + 1;
+        }
+    }
+    line.len()
+}
+
+/// End of a shell variable: `$name`, `${...}`, `$(...)` is left to its contents.
+fn variable_end(line: &str, start: usize) -> usize {
+    let rest = &line[start + 1..];
+    if rest.starts_with('{') {
+        return rest.find('}').map_or(line.len(), |i| start + 1 + i + 1);
+    }
+    let length = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    // Special parameters such as $? or $@
+    let length = if length == 0 && rest.starts_with(['?', '@', '#', '*', '!', '$']) {
+        1
+    } else {
+        length
+    };
+    start + 1 + length
+}
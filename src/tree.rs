@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::app::{FileItem, Selection};
+use crate::utils::FileStatus;
+
+/// A line of the status list: either a file from `App.files` or, in tree mode, a directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Row {
+    File {
+        /// Index into `App.files`.
+        index: usize,
+        depth: usize,
+    },
+    Dir {
+        /// Full path relative to the destination directory, without a trailing slash.
+        path: String,
+        /// What is displayed; single-child directory chains are folded into one row.
+        name: String,
+        depth: usize,
+        collapsed: bool,
+        /// Indices into `App.files` of every file below this directory.
+        files: Vec<usize>,
+    },
+}
+
+impl Row {
+    /// Path identifying the row, used to keep the highlight in place across rebuilds.
+    pub fn path<'a>(&'a self, files: &'a [FileItem]) -> &'a str {
+        match self {
+            Row::File { index, .. } => &files[*index].path,
+            Row::Dir { path, .. } => path,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        match self {
+            Row::File { depth, .. } | Row::Dir { depth, .. } => *depth,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    dirs: BTreeMap<String, Node>,
+    files: Vec<usize>,
+}
+
+impl Node {
+    fn descendants(&self) -> Vec<usize> {
+        let mut files: Vec<usize> = self.dirs.values().flat_map(Node::descendants).collect();
+        files.extend(&self.files);
+        files
+    }
+}
+
+/// One row per file, in `chezmoi status` order.
+pub fn flat_rows(indices: impl Iterator<Item = usize>) -> Vec<Row> {
+    indices.map(|index| Row::File { index, depth: 0 }).collect()
+}
+
+/// Groups the given files by directory, directories first, skipping the contents of
+/// `collapsed` ones.
+pub fn tree_rows(
+    files: &[FileItem],
+    indices: impl Iterator<Item = usize>,
+    collapsed: &HashSet<String>,
+) -> Vec<Row> {
+    let mut root = Node::default();
+    for index in indices {
+        let mut node = &mut root;
+        let mut components: Vec<&str> = files[index].path.split('/').collect();
+        components.pop();
+        for component in components {
+            node = node.dirs.entry(component.to_string()).or_default();
+        }
+        node.files.push(index);
+    }
+
+    let mut rows = Vec::new();
+    flatten(&root, "", 0, collapsed, &mut rows);
+    rows
+}
+
+fn flatten(
+    node: &Node,
+    prefix: &str,
+    depth: usize,
+    collapsed: &HashSet<String>,
+    rows: &mut Vec<Row>,
+) {
+    for (name, child) in &node.dirs {
+        let mut name = name.clone();
+        let mut child = child;
+        while child.files.is_empty() && child.dirs.len() == 1 {
+            let (grandchild_name, grandchild) = child.dirs.iter().next().unwrap();
+            name = format!("{}/{}", name, grandchild_name);
+            child = grandchild;
+        }
+        let path = format!("{}{}", prefix, name);
+        let is_collapsed = collapsed.contains(&path);
+        rows.push(Row::Dir {
+            path: path.clone(),
+            name,
+            depth,
+            collapsed: is_collapsed,
+            files: child.descendants(),
+        });
+        if !is_collapsed {
+            flatten(child, &format!("{}/", path), depth + 1, collapsed, rows);
+        }
+    }
+    for &index in &node.files {
+        rows.push(Row::File { index, depth });
+    }
+}
+
+/// Status shown on a directory: the status shared by all its files, or modified when they
+/// differ.
+pub fn aggregate_status<'a>(statuses: impl Iterator<Item = &'a FileStatus>) -> FileStatus {
+    let mut aggregate: Option<&FileStatus> = None;
+    for status in statuses.filter(|s| **s != FileStatus::Unchanged) {
+        match aggregate {
+            None => aggregate = Some(status),
+            Some(current) if current != status => return FileStatus::Modified,
+            Some(_) => {}
+        }
+    }
+    aggregate.cloned().unwrap_or(FileStatus::Unchanged)
+}
+
+/// Selection marker of a directory: `L`/`S` when every file shares it, `*` when only some
+/// files are selected.
+pub fn aggregate_selection<'a>(selections: impl Iterator<Item = &'a Selection>) -> &'static str {
+    let selections: Vec<&Selection> = selections.collect();
+    if selections.iter().all(|s| **s == Selection::None) {
+        " "
+    } else if selections.iter().all(|s| **s == Selection::Local) {
+        "L"
+    } else if selections.iter().all(|s| **s == Selection::Source) {
+        "S"
+    } else {
+        "*"
+    }
+}
@@ -4,30 +4,44 @@ pub enum FileStatus {
     Added,
     Deleted,
     Untracked,
+    /// A script that will run on the next `chezmoi apply`.
+    Run,
     Unchanged,
 }
 
-pub fn extract_filename_and_status(line: &str) -> (String, FileStatus, FileStatus) {
-    let status_chars = &line[0..2];
-    let path = line[2..].trim().to_string();
+impl FileStatus {
+    /// Maps one column of `chezmoi status` output to a status, `None` for unknown codes.
+    pub fn from_code(code: char) -> Option<Self> {
+        match code {
+            'M' => Some(FileStatus::Modified),
+            'A' => Some(FileStatus::Added),
+            'D' => Some(FileStatus::Deleted),
+            '?' => Some(FileStatus::Untracked),
+            'R' => Some(FileStatus::Run),
+            ' ' => Some(FileStatus::Unchanged),
+            _ => None,
+        }
+    }
+}
 
-    // Extract local status (first char)
-    let local_status = match status_chars.chars().next().unwrap_or(' ') {
-        'M' => FileStatus::Modified,
-        'A' => FileStatus::Added,
-        'D' => FileStatus::Deleted,
-        '?' => FileStatus::Untracked,
-        _ => FileStatus::Unchanged,
+/// Parses one line of `chezmoi status` into its path, local status and source status.
+pub fn extract_filename_and_status(line: &str) -> Result<(String, FileStatus, FileStatus), String> {
+    let mut chars = line.chars();
+    let (Some(local_code), Some(source_code)) = (chars.next(), chars.next()) else {
+        return Err(format!("status line too short: {:?}", line));
     };
+    let path = chars.as_str().trim().to_string();
+    if path.is_empty() {
+        return Err(format!("status line has no path: {:?}", line));
+    }
+
+    // Extract local status (first char)
+    let local_status = FileStatus::from_code(local_code)
+        .ok_or_else(|| format!("unknown status code {:?} in {:?}", local_code, line))?;
 
     // Extract source status (second char)
-    let source_status = match status_chars.chars().nth(1).unwrap_or(' ') {
-        'M' => FileStatus::Modified,
-        'A' => FileStatus::Added,
-        'D' => FileStatus::Deleted,
-        '?' => FileStatus::Untracked,
-        _ => FileStatus::Unchanged,
-    };
+    let source_status = FileStatus::from_code(source_code)
+        .ok_or_else(|| format!("unknown status code {:?} in {:?}", source_code, line))?;
 
-    (path, local_status, source_status)
+    Ok((path, local_status, source_status))
 }
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::app::FileItem;
+use crate::chezmoi::{self, AddOption, ChezmoiBackend, ChezmoiResult, EntryTypeFilter};
+use crate::discard::{self, DiscardPlan, DiscardSide};
+use crate::git::{self, Commit, GitCommand, GitStatus};
+use crate::hunks::{self, PatchSide};
+use crate::plan::{self, ExecutionPlan};
+
+/// Number of threads computing per-file diffs in parallel.
+const DIFF_THREADS: usize = 4;
+
+/// What the right pane shows for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreviewKind {
+    /// `chezmoi diff`
+    Diff,
+    /// Target state contents from `chezmoi cat`, for managed files in sync.
+    Contents,
+    /// The file as it is on disk, for unmanaged files.
+    Target,
+}
+
+/// A chezmoi command to run off the UI thread.
+#[derive(Debug)]
+pub enum Job {
+    Status(EntryTypeFilter),
+    /// Fetch what the right pane shows for a single file. `epoch` is echoed back so results
+    /// computed before a cache invalidation can be told apart.
+    Diff {
+        path: String,
+        generation: u64,
+        epoch: u64,
+        kind: PreviewKind,
+        /// Also fetch the diff as coloured by the user's external diff tool.
+        external: bool,
+    },
+    /// Run one `chezmoi diff` over everything and split it per file.
+    BulkDiff {
+        epoch: u64,
+    },
+    /// Work out what executing both staging batches would do, for confirmation.
+    Plan {
+        re_add: Vec<String>,
+        apply: Vec<String>,
+        /// Apply paths that are scripts to run.
+        scripts: Vec<String>,
+    },
+    /// Run both staging batches one file at a time: re-add first, then apply, which is
+    /// skipped when any re-add failed so local changes aren't overwritten.
+    Execute {
+        re_add: Vec<String>,
+        apply: Vec<String>,
+    },
+    Managed(EntryTypeFilter),
+    Forget(Vec<String>),
+    Chattr {
+        attributes: String,
+        paths: Vec<String>,
+    },
+    Unmanaged(String),
+    Add {
+        options: Vec<AddOption>,
+        paths: Vec<String>,
+    },
+    /// Work out what discarding one side of a file's changes would do, for confirmation.
+    DiscardPlan {
+        path: String,
+        side: DiscardSide,
+    },
+    Discard(DiscardPlan),
+    /// Write the hunks of `diff` at the given indices to the source file or target of `path`.
+    PatchHunks {
+        path: String,
+        diff: String,
+        hunks: Vec<usize>,
+        side: PatchSide,
+    },
+    /// `git status` of the source directory, for the git panel.
+    GitStatus,
+    Git(GitCommand),
+    /// Pull the source repository without applying. `apply_all` is echoed back.
+    Update {
+        apply_all: bool,
+    },
+}
+
+/// The outcome of each file of a batch; `None` when the file was skipped.
+pub type FileResults = Vec<(String, Option<ChezmoiResult<()>>)>;
+
+/// The outcome of a [`Job`], sent back to `App`.
+#[derive(Debug)]
+pub enum JobOutput {
+    Status(ChezmoiResult<Vec<FileItem>>),
+    /// `result` is `None` when the job was skipped because a newer diff was requested.
+    Diff {
+        path: String,
+        kind: PreviewKind,
+        epoch: u64,
+        result: Option<ChezmoiResult<String>>,
+        /// External rendering of a diff; on failure the built-in colouring is used.
+        rendered: Option<String>,
+    },
+    BulkDiff {
+        epoch: u64,
+        result: ChezmoiResult<HashMap<String, String>>,
+    },
+    Plan(ChezmoiResult<ExecutionPlan>),
+    Execute {
+        re_add: FileResults,
+        apply: FileResults,
+    },
+    Managed(ChezmoiResult<Vec<String>>),
+    Forget(ChezmoiResult<()>),
+    Chattr(ChezmoiResult<()>),
+    Unmanaged(ChezmoiResult<Vec<String>>),
+    Add(ChezmoiResult<()>),
+    DiscardPlan(ChezmoiResult<DiscardPlan>),
+    Discard(ChezmoiResult<()>),
+    PatchHunks(ChezmoiResult<()>),
+    GitStatus(ChezmoiResult<GitStatus>),
+    Git {
+        command: GitCommand,
+        result: ChezmoiResult<()>,
+    },
+    Update {
+        apply_all: bool,
+        result: ChezmoiResult<Vec<Commit>>,
+    },
+}
+
+/// Runs chezmoi commands on background threads.
+///
+/// Status, managed, bulk diff and every command that writes run one at a time in the order they were queued.
+/// Per-file diffs are read-only and go to a small pool of threads so neighbouring files can
+/// be prefetched in parallel. Each diff job carries a generation number: when a newer diff
+/// has been requested by the time it is picked up, it is skipped instead of spawning chezmoi
+/// for nothing.
+#[derive(Debug)]
+pub struct Worker {
+    jobs: Sender<Job>,
+    diffs: Sender<Job>,
+    outputs: Receiver<JobOutput>,
+    latest_diff: Arc<AtomicU64>,
+    in_flight: usize,
+}
+
+impl Worker {
+    pub fn new(backend: Arc<dyn ChezmoiBackend>) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (diffs, diff_receiver) = mpsc::channel::<Job>();
+        let (output_sender, outputs) = mpsc::channel();
+        let latest_diff = Arc::new(AtomicU64::new(0));
+
+        let serial_backend = Arc::clone(&backend);
+        let serial_sender = output_sender.clone();
+        thread::spawn(move || {
+            for job in job_receiver {
+                if serial_sender.send(run_job(&*serial_backend, job)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let diff_receiver = Arc::new(Mutex::new(diff_receiver));
+        for _ in 0..DIFF_THREADS {
+            let backend = Arc::clone(&backend);
+            let receiver = Arc::clone(&diff_receiver);
+            let sender = output_sender.clone();
+            let latest = Arc::clone(&latest_diff);
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let output = match job {
+                    Job::Diff {
+                        path,
+                        generation,
+                        epoch,
+                        kind,
+                        ..
+                    } if generation < latest.load(Ordering::Relaxed) => JobOutput::Diff {
+                        path,
+                        kind,
+                        epoch,
+                        result: None,
+                        rendered: None,
+                    },
+                    job => run_job(&*backend, job),
+                };
+                if sender.send(output).is_err() {
+                    break;
+                }
+            });
+        }
+
+        Self {
+            jobs,
+            diffs,
+            outputs,
+            latest_diff,
+            in_flight: 0,
+        }
+    }
+
+    pub fn submit(&mut self, job: Job) {
+        let sent = if let Job::Diff { generation, .. } = &job {
+            self.latest_diff.fetch_max(*generation, Ordering::Relaxed);
+            self.diffs.send(job).is_ok()
+        } else {
+            self.jobs.send(job).is_ok()
+        };
+        if sent {
+            self.in_flight += 1;
+        }
+    }
+
+    /// Returns the next finished job, if any, without blocking.
+    pub fn try_recv(&mut self) -> Option<JobOutput> {
+        match self.outputs.try_recv() {
+            Ok(output) => {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                Some(output)
+            }
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => None,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.in_flight > 0
+    }
+}
+
+fn run_job(backend: &dyn ChezmoiBackend, job: Job) -> JobOutput {
+    match job {
+        Job::Status(types) => JobOutput::Status(backend.status(&types)),
+        Job::Diff {
+            path,
+            epoch,
+            kind,
+            external,
+            ..
+        } => {
+            let result = Some(match kind {
+                PreviewKind::Diff => backend.diff(&path),
+                PreviewKind::Contents => backend.cat(&path),
+                PreviewKind::Target => backend.read_target(&path),
+            });
+            let rendered = (kind == PreviewKind::Diff && external)
+                .then(|| backend.diff_external(&path).ok())
+                .flatten();
+            JobOutput::Diff {
+                path,
+                kind,
+                epoch,
+                result,
+                rendered,
+            }
+        }
+        Job::BulkDiff { epoch } => JobOutput::BulkDiff {
+            epoch,
+            result: backend.diff_all().map(|diff| chezmoi::split_diff(&diff)),
+        },
+        Job::Plan {
+            re_add,
+            apply,
+            scripts,
+        } => JobOutput::Plan(plan::build(backend, &re_add, &apply, &scripts)),
+        Job::Execute { re_add, apply } => {
+            // One process per file, so a failure can be pinned on the file that caused it
+            let re_add: FileResults = re_add
+                .into_iter()
+                .map(|path| {
+                    let result = backend.re_add(std::slice::from_ref(&path));
+                    (path, Some(result))
+                })
+                .collect();
+            let re_add_failed = re_add
+                .iter()
+                .any(|(_, result)| matches!(result, Some(Err(_))));
+            // The plan listing the targets to overwrite, conflicts included, was confirmed:
+            // chezmoi must not ask again for targets changed since it last wrote them
+            let apply: FileResults = apply
+                .into_iter()
+                .map(|path| {
+                    let result =
+                        (!re_add_failed).then(|| backend.force_apply(std::slice::from_ref(&path)));
+                    (path, result)
+                })
+                .collect();
+            JobOutput::Execute { re_add, apply }
+        }
+        Job::Managed(types) => JobOutput::Managed(backend.managed(&types)),
+        Job::Forget(files) => JobOutput::Forget(backend.forget(&files)),
+        Job::Chattr { attributes, paths } => JobOutput::Chattr(backend.chattr(&attributes, &paths)),
+        Job::Unmanaged(dir) => JobOutput::Unmanaged(backend.unmanaged(&dir)),
+        Job::Add { options, paths } => JobOutput::Add(backend.add(&options, &paths)),
+        Job::DiscardPlan { path, side } => {
+            JobOutput::DiscardPlan(discard::plan(backend, &path, side))
+        }
+        Job::Discard(plan) => JobOutput::Discard(discard::execute(backend, &plan)),
+        Job::PatchHunks {
+            path,
+            diff,
+            hunks,
+            side,
+        } => JobOutput::PatchHunks(hunks::patch_file(backend, &path, &diff, &hunks, side)),
+        Job::GitStatus => JobOutput::GitStatus(git::status(backend)),
+        Job::Git(command) => {
+            let result = git::run(backend, &command);
+            JobOutput::Git { command, result }
+        }
+        Job::Update { apply_all } => JobOutput::Update {
+            apply_all,
+            result: git::update(backend),
+        },
+    }
+}